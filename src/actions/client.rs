@@ -1,35 +1,134 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
 
 use base64::{Engine, prelude::BASE64_STANDARD_NO_PAD};
 use chrono::Utc;
-use log::info;
-use serde_json::{Value, json};
+use log::{info, warn};
+use semver::Version;
+use serde_json::{Map, Value, json};
+use tokio::task::JoinSet;
 
 use crate::{
-    errors::InstallerError,
+    errors::{InstallerError, PathContext},
     net::{
-        manifest::{self, MinecraftVersion},
+        self, Checksum, GameSide,
+        library::Library,
+        manifest::{self, AssetObjects, MinecraftVersion, VersionDownload, VersionJson},
         meta::{self, LoaderType, LoaderVersion},
     },
+    progress::{Progress, ProgressEvent},
+    security::safe_join,
+};
+
+use super::{
+    installed::{InstallKind, Installation},
+    launcher_profiles::{self, LauncherProfiles},
 };
 
+/// Customizations of the launcher profile created by [install]
+#[derive(Clone, Default)]
+pub struct ProfileOptions {
+    /// Name of the profile, `Ornithe (<Loader>) <version>` by default
+    pub name: Option<String>,
+    /// PNG file to use as the icon instead of the Ornithe logo
+    pub icon: Option<PathBuf>,
+    pub java_args: Option<String>,
+    /// Java executable to launch the game with
+    pub java_dir: Option<PathBuf>,
+    /// Initial window width and height
+    pub resolution: Option<(u32, u32)>,
+    /// Directory to keep the game files in, instead of the launcher directory
+    pub game_dir: Option<PathBuf>,
+}
+
+impl ProfileOptions {
+    /// The profile fields set by these options, other than the name.
+    /// Only checks the options, the game directory is created by [install].
+    fn fields(&self) -> Result<Map<String, Value>, InstallerError> {
+        let mut fields = Map::new();
+        if let Some(icon) = &self.icon {
+            fields.insert("icon".to_owned(), Value::String(read_icon(icon)?));
+        }
+        if let Some(java_args) = &self.java_args {
+            fields.insert("javaArgs".to_owned(), Value::String(java_args.clone()));
+        }
+        if let Some(java_dir) = &self.java_dir {
+            let java_dir = std::path::absolute(java_dir).with_path(java_dir)?;
+            fields.insert(
+                "javaDir".to_owned(),
+                Value::String(java_dir.to_string_lossy().into_owned()),
+            );
+        }
+        if let Some((width, height)) = self.resolution {
+            fields.insert(
+                "resolution".to_owned(),
+                json!({ "width": width, "height": height }),
+            );
+        }
+        if let Some(game_dir) = &self.game_dir {
+            if game_dir.exists() && !game_dir.is_dir() {
+                return Err(InstallerError::UserInput(format!(
+                    "{} is not a directory",
+                    game_dir.display()
+                )));
+            }
+            let game_dir = std::path::absolute(game_dir).with_path(game_dir)?;
+            fields.insert(
+                "gameDir".to_owned(),
+                Value::String(game_dir.to_string_lossy().into_owned()),
+            );
+        }
+        Ok(fields)
+    }
+}
+
+/// Parses a window resolution given as `<width>x<height>`
+pub fn parse_resolution(value: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid resolution {}, expected e.g. 1280x720", value);
+    let (width, height) = value.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+    match (width.trim().parse(), height.trim().parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Installs Ornithe for the official launcher, returning the launcher profile
+/// files that were updated. No profile is created if `profile` is `None`.
+/// With `predownload`, everything needed to start the game is downloaded
+/// instead of leaving it to the launcher.
 pub async fn install(
     version: MinecraftVersion,
     loader_type: LoaderType,
     loader_version: LoaderVersion,
     location: PathBuf,
-    create_profile: bool,
-) -> Result<(), InstallerError> {
+    profile: Option<ProfileOptions>,
+    predownload: bool,
+    progress: Progress,
+) -> Result<Vec<PathBuf>, InstallerError> {
+    // Check the profile options before changing anything
+    let profile_fields = profile.as_ref().map(ProfileOptions::fields).transpose()?;
+    if profile
+        .as_ref()
+        .and_then(|profile| profile.name.as_ref())
+        .is_some_and(|name| name.trim().is_empty())
+    {
+        return Err(InstallerError::UserInput(
+            "The profile name must not be empty".to_owned(),
+        ));
+    }
+
     if !location.exists() {
-        std::fs::create_dir_all(&location)?;
+        std::fs::create_dir_all(&location).with_path(&location)?;
     }
-    let location = location.canonicalize()?;
+    let location = location.canonicalize().with_path(&location)?;
     info!(
         "Installing Minecraft client at {}",
         location.to_str().unwrap_or("<not representable>")
     );
 
-    info!("Fetching launch jsons..");
+    progress.phase("Fetching launch jsons..");
     let vanilla_launch_json = manifest::fetch_launch_json(&version).await?;
 
     let ornithe_launch_json = meta::fetch_launch_json(
@@ -40,111 +139,890 @@ pub async fn install(
     )
     .await?;
 
-    info!("Setting up destination..");
+    progress.phase("Setting up destination..");
 
     let vanilla_profile_name = version.id.to_string() + "-vanilla";
-    let profile_name = format!(
-        "{}-loader-{}-{}-ornithe",
-        loader_type.get_name(),
-        loader_version.version,
-        version.id
-    );
+    let profile_name = version_name(&loader_type, &loader_version, &version);
 
     let versions_dir = location.join("versions");
-    let vanilla_profile_dir = versions_dir.join(&vanilla_profile_name);
+    let vanilla_profile_dir = safe_join(&versions_dir, &vanilla_profile_name)?;
     let vanilla_profile_json = vanilla_profile_dir.join(vanilla_profile_name.clone() + ".json");
-    let profile_dir = versions_dir.join(&profile_name);
+    let vanilla_profile_jar = vanilla_profile_dir.join(vanilla_profile_name.clone() + ".jar");
+    let profile_dir = safe_join(&versions_dir, &profile_name)?;
     let profile_json = profile_dir.join(profile_name.clone() + ".json");
 
-    if std::fs::exists(&vanilla_profile_dir).unwrap_or_default() {
-        std::fs::remove_dir_all(&vanilla_profile_dir)?;
-    }
     if std::fs::exists(&profile_dir).unwrap_or_default() {
         std::fs::remove_dir_all(&profile_dir)?;
     }
 
-    info!("Creating files..");
+    progress.phase("Creating files..");
 
-    create_empty_jar(&vanilla_profile_dir, &vanilla_profile_name)?;
+    create_vanilla_dir(
+        &vanilla_profile_dir,
+        &vanilla_profile_jar,
+        vanilla_launch_json.downloads.get("client"),
+    )?;
     create_empty_jar(&profile_dir, &profile_name)?;
 
-    std::fs::write(vanilla_profile_json, vanilla_launch_json)?;
-    std::fs::write(profile_json, ornithe_launch_json)?;
+    std::fs::write(
+        &vanilla_profile_json,
+        serde_json::to_string_pretty(&vanilla_launch_json)?,
+    )
+    .with_path(&vanilla_profile_json)?;
+    std::fs::write(
+        &profile_json,
+        serde_json::to_string_pretty(&ornithe_launch_json)?,
+    )
+    .with_path(&profile_json)?;
 
-    if create_profile {
-        update_profiles(location, profile_name, version, loader_type)?;
+    if predownload {
+        download_game_files(
+            &location,
+            &version,
+            &vanilla_launch_json,
+            &ornithe_launch_json.libraries,
+            &vanilla_profile_jar,
+            &progress,
+        )
+        .await?;
     }
 
-    Ok(())
+    let (Some(profile), Some(profile_fields)) = (profile, profile_fields) else {
+        return Ok(Vec::new());
+    };
+    let launcher_profile_name = profile.name.unwrap_or_else(|| {
+        "Ornithe (".to_owned() + loader_type.get_localized_name() + ") " + &version.id
+    });
+    let updated = update_profiles(
+        &location,
+        &launcher_profile_name,
+        &profile_name,
+        &profile_fields,
+    )?;
+    if let Some(game_dir) = &profile.game_dir {
+        std::fs::create_dir_all(game_dir).with_path(game_dir)?;
+    }
+    Ok(updated)
 }
 
-fn create_empty_jar(dir: &PathBuf, name: &String) -> Result<(), InstallerError> {
-    std::fs::create_dir_all(dir)?;
-    std::fs::File::create(dir.join(name.clone() + ".jar"))?;
-    Ok(())
+/// An Ornithe version installed into the versions directory of the official launcher
+pub struct InstalledVersion {
+    /// Name of the version directory, e.g. `fabric-loader-0.16.10-1.8.9-ornithe`
+    pub name: String,
+    pub loader_name: String,
+    pub loader_version: String,
+    pub minecraft_version: String,
 }
 
-fn update_profiles(
-    game_dir: PathBuf,
-    name: String,
-    version: MinecraftVersion,
-    loader_type: LoaderType,
+/// Lists the Ornithe versions installed at the given location.
+/// Versions without their vanilla stub are not recognized.
+pub fn find_installed(location: &Path) -> Result<Vec<InstalledVersion>, InstallerError> {
+    let versions_dir = location.join("versions");
+    if !versions_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut vanilla = Vec::new();
+    let mut ornithe = Vec::new();
+    for entry in std::fs::read_dir(&versions_dir).with_path(&versions_dir)? {
+        let entry = entry.with_path(&versions_dir)?;
+        let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+            continue;
+        };
+        if let Some(id) = name.strip_suffix("-vanilla") {
+            vanilla.push(id.to_owned());
+        } else if name.ends_with("-ornithe") {
+            ornithe.push(name);
+        }
+    }
+    Ok(ornithe
+        .into_iter()
+        .filter_map(|name| parse_version_name(name, &vanilla))
+        .collect())
+}
+
+/// Finds the Ornithe versions installed at the given location,
+/// along with the launcher profiles that use them
+pub fn find_installations(location: &Path) -> Result<Vec<Installation>, InstallerError> {
+    let installed = find_installed(location)?;
+    if installed.is_empty() {
+        return Ok(Vec::new());
+    }
+    let profiles = read_profile_versions(location).unwrap_or_else(|e| {
+        warn!("Failed to read launcher profiles: {}", e.report());
+        Vec::new()
+    });
+
+    let versions_dir = location.join("versions");
+    Ok(installed
+        .into_iter()
+        .map(|version| Installation {
+            kind: InstallKind::Client,
+            path: versions_dir.join(&version.name),
+            profiles: profiles
+                .iter()
+                .filter(|(id, _)| *id == version.name)
+                .map(|(_, name)| name.clone())
+                .collect(),
+            minecraft_version: version.minecraft_version,
+            loader_name: version.loader_name,
+            loader_version: version.loader_version,
+        })
+        .collect())
+}
+
+/// Reads the version id and name of each launcher profile, from all profile files
+fn read_profile_versions(location: &Path) -> Result<Vec<(String, String)>, InstallerError> {
+    let mut out = Vec::new();
+    for file in LauncherProfiles::open_existing(location)? {
+        for (key, profile) in file.profiles() {
+            if let Some(id) = profile["lastVersionId"].as_str() {
+                let name = profile["name"].as_str().unwrap_or(key);
+                let entry = (id.to_owned(), name.to_owned());
+                if !out.contains(&entry) {
+                    out.push(entry);
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// An Ornithe version used by launcher profiles that can be upgraded, see [plan_upgrades]
+pub struct Upgrade {
+    pub installed: InstalledVersion,
+    pub minecraft_version: MinecraftVersion,
+    pub loader_type: LoaderType,
+    pub loader_version: LoaderVersion,
+}
+
+/// Finds the Ornithe versions used by launcher profiles, optionally only those of
+/// one Minecraft version or loader, and selects the loader version to upgrade
+/// them to. Versions that already use the selected loader version are left out.
+pub async fn plan_upgrades(
+    location: &Path,
+    available: &[MinecraftVersion],
+    selector: &str,
+    minecraft_version: Option<&str>,
+    loader_type: Option<&LoaderType>,
+) -> Result<Vec<Upgrade>, InstallerError> {
+    let profiles = read_profile_versions(location)?;
+    let installed = find_installed(location)?
+        .into_iter()
+        .filter(|version| profiles.iter().any(|(id, _)| *id == version.name))
+        .filter(|version| {
+            minecraft_version.is_none_or(|id| version.minecraft_version == id)
+                && loader_type.is_none_or(|loader| version.loader_name == loader.get_name())
+        })
+        .collect::<Vec<_>>();
+    if installed.is_empty() {
+        return Err(InstallerError::NotFound(format!(
+            "No matching Ornithe profiles found in {}",
+            location.display()
+        )));
+    }
+
+    let mut upgrades = Vec::new();
+    for version in installed {
+        let Some(loader_type) = LoaderType::find(&version.loader_name) else {
+            warn!(
+                "Skipping {}: unknown loader {}",
+                version.name, version.loader_name
+            );
+            continue;
+        };
+        let Some(minecraft_version) = available.iter().find(|v| v.id == version.minecraft_version)
+        else {
+            warn!(
+                "Skipping {}: Minecraft {} is not supported",
+                version.name, version.minecraft_version
+            );
+            continue;
+        };
+        let loader_versions = meta::fetch_compatible_loader_versions_type(
+            &loader_type,
+            minecraft_version,
+            GameSide::Client,
+        )
+        .await?;
+        let loader_version = meta::select_loader_version(&loader_versions, selector)?;
+        if loader_version.version == version.loader_version {
+            info!("{} is up to date", version.name);
+            continue;
+        }
+        if is_downgrade(&version.loader_version, &loader_version.version, selector) {
+            warn!(
+                "Skipping {}: installed loader {} is newer than {}, select {} explicitly to downgrade",
+                version.name,
+                version.loader_version,
+                loader_version.version,
+                loader_version.version
+            );
+            continue;
+        }
+        upgrades.push(Upgrade {
+            minecraft_version: minecraft_version.clone(),
+            loader_type,
+            loader_version: loader_version.clone(),
+            installed: version,
+        });
+    }
+    Ok(upgrades)
+}
+
+/// Whether upgrading from `installed` to `selected` would install an older loader
+/// without the user having asked for that exact version.
+fn is_downgrade(installed: &str, selected: &str, selector: &str) -> bool {
+    if selector.trim() == selected {
+        return false;
+    }
+    match (Version::parse(installed), Version::parse(selected)) {
+        (Ok(installed), Ok(selected)) => selected < installed,
+        _ => false,
+    }
+}
+
+/// Installs the new loader version of an upgrade and repoints the launcher profiles
+/// using the old version to it. With `prune`, the old version directory is removed.
+/// Returns the name of the new version.
+pub async fn upgrade(
+    location: PathBuf,
+    upgrade: Upgrade,
+    prune: bool,
+    progress: Progress,
+) -> Result<String, InstallerError> {
+    let name = version_name(
+        &upgrade.loader_type,
+        &upgrade.loader_version,
+        &upgrade.minecraft_version,
+    );
+    install(
+        upgrade.minecraft_version,
+        upgrade.loader_type,
+        upgrade.loader_version,
+        location.clone(),
+        None,
+        false,
+        progress,
+    )
+    .await?;
+    repoint_profiles(&location, &upgrade.installed.name, &name)?;
+
+    if prune {
+        let dir = safe_join(&location.join("versions"), &upgrade.installed.name)?;
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).with_path(&dir)?;
+            info!("Removed {}", upgrade.installed.name);
+        }
+    }
+    Ok(name)
+}
+
+fn repoint_profiles(game_dir: &Path, from: &str, to: &str) -> Result<Vec<PathBuf>, InstallerError> {
+    launcher_profiles::edit_all(game_dir, false, |profiles| {
+        let mut changed = false;
+        for profile in profiles.values_mut() {
+            if profile["lastVersionId"] == from {
+                profile["lastVersionId"] = Value::String(to.to_owned());
+                changed = true;
+            }
+        }
+        Ok(changed)
+    })
+}
+
+fn version_name(
+    loader_type: &LoaderType,
+    loader_version: &LoaderVersion,
+    version: &MinecraftVersion,
+) -> String {
+    format!(
+        "{}-loader-{}-{}-ornithe",
+        loader_type.get_name(),
+        loader_version.version,
+        version.id
+    )
+}
+
+fn parse_version_name(name: String, vanilla: &[String]) -> Option<InstalledVersion> {
+    let (loader_name, rest) = name.split_once("-loader-")?;
+    let rest = rest.strip_suffix("-ornithe")?;
+    // Loader and game versions may both contain dashes, so the
+    // game version is found by matching the installed vanilla stubs
+    let minecraft_version = vanilla
+        .iter()
+        .filter(|id| {
+            rest.strip_suffix(id.as_str())
+                .and_then(|loader_version| loader_version.strip_suffix('-'))
+                .is_some_and(|loader_version| !loader_version.is_empty())
+        })
+        .max_by_key(|id| id.len())?;
+    let loader_version = &rest[..rest.len() - minecraft_version.len() - 1];
+    Some(InstalledVersion {
+        loader_name: loader_name.to_owned(),
+        loader_version: loader_version.to_owned(),
+        minecraft_version: minecraft_version.clone(),
+        name,
+    })
+}
+
+/// Removes installed Ornithe versions along with their launcher profiles, optionally
+/// only those of one Minecraft version or loader. Vanilla stubs are kept as long as
+/// other Ornithe versions depend on them. Returns the names of the removed versions.
+pub fn uninstall(
+    location: &Path,
+    minecraft_version: Option<&str>,
+    loader_type: Option<&LoaderType>,
+) -> Result<Vec<String>, InstallerError> {
+    let (removed, kept): (Vec<_>, Vec<_>) =
+        find_installed(location)?.into_iter().partition(|version| {
+            minecraft_version.is_none_or(|id| version.minecraft_version == id)
+                && loader_type.is_none_or(|loader| version.loader_name == loader.get_name())
+        });
+    if removed.is_empty() {
+        return Err(InstallerError::NotFound(format!(
+            "No matching Ornithe versions are installed in {}",
+            location.display()
+        )));
+    }
+
+    // Profiles are removed first, as editing them either succeeds for
+    // every file or changes nothing, which keeps the versions in place
+    let names = removed
+        .iter()
+        .map(|version| version.name.clone())
+        .collect::<Vec<_>>();
+    remove_profiles(location, &names)?;
+
+    let versions_dir = location.join("versions");
+    for version in &removed {
+        let dir = safe_join(&versions_dir, &version.name)?;
+        std::fs::remove_dir_all(&dir).with_path(&dir)?;
+        info!(
+            "Removed {} (loader {} {} for Minecraft {})",
+            version.name, version.loader_name, version.loader_version, version.minecraft_version
+        );
+
+        if !kept
+            .iter()
+            .any(|v| v.minecraft_version == version.minecraft_version)
+        {
+            let vanilla_dir = safe_join(
+                &versions_dir,
+                &(version.minecraft_version.clone() + "-vanilla"),
+            )?;
+            if vanilla_dir.exists() {
+                std::fs::remove_dir_all(&vanilla_dir).with_path(&vanilla_dir)?;
+            }
+        }
+    }
+    Ok(names)
+}
+
+fn remove_profiles(game_dir: &Path, names: &[String]) -> Result<Vec<PathBuf>, InstallerError> {
+    launcher_profiles::edit_all(game_dir, false, |profiles| {
+        let count = profiles.len();
+        profiles.retain(|_, profile| {
+            !profile
+                .get("lastVersionId")
+                .and_then(Value::as_str)
+                .is_some_and(|id| names.iter().any(|name| name == id))
+        });
+        Ok(profiles.len() != count)
+    })
+}
+
+/// A file to download into the launcher directory
+struct Download {
+    url: String,
+    file: PathBuf,
+    /// Looked up in the maven repository of the file if not known
+    checksum: Option<Checksum>,
+}
+
+/// Downloads the libraries, client jar and assets of a version, so that the
+/// launcher can start it without network access. Intact files are kept.
+async fn download_game_files(
+    location: &Path,
+    version: &MinecraftVersion,
+    vanilla_launch_json: &VersionJson,
+    ornithe_libraries: &[Library],
+    client_jar: &Path,
+    progress: &Progress,
 ) -> Result<(), InstallerError> {
-    let launcher_profiles_path = game_dir.join("launcher_profiles.json");
-
-    match std::fs::read_to_string(launcher_profiles_path.clone()) {
-        Ok(launcher_profiles) => match serde_json::from_str::<Value>(&launcher_profiles) {
-            Ok(mut json) => {
-                let raw_profiles = json.as_object_mut().unwrap().get_mut("profiles").unwrap();
-                if !raw_profiles.is_object() {
-                    return Err(InstallerError(
-                        "\"profiles\" field must be an object".to_string(),
-                    ));
+    progress.phase("Downloading libraries..");
+    let libraries_dir = location.join("libraries");
+    let mut libraries: Vec<Download> = Vec::new();
+    let mut queued = HashSet::new();
+    for library in vanilla_launch_json
+        .libraries
+        .iter()
+        .chain(ornithe_libraries)
+    {
+        for library_file in library.files()? {
+            let file = safe_join(&libraries_dir, &library_file.path)?;
+            if queued.insert(file.clone()) {
+                libraries.push(Download {
+                    url: library_file.url,
+                    file,
+                    checksum: library_file.checksum,
+                });
+            }
+        }
+    }
+    progress.send(ProgressEvent::LibrariesQueued(libraries.len()));
+    download_all(libraries, Some(ProgressEvent::LibraryCompleted), progress).await?;
+
+    progress.phase("Downloading client jar..");
+    let client = match vanilla_launch_json.downloads.get("client") {
+        Some(client) => client.clone(),
+        None => version.get_jar_download_url(&GameSide::Client).await?,
+    };
+    net::download_file_if_changed(&client.url, client_jar, Some(&client.checksum()), progress)
+        .await?;
+
+    let Some(asset_index) = &vanilla_launch_json.asset_index else {
+        warn!("Minecraft {} has no asset index", version.id);
+        return Ok(());
+    };
+    progress.phase("Downloading assets..");
+    let assets_dir = location.join("assets");
+    let index_file = safe_join(
+        &assets_dir.join("indexes"),
+        &(asset_index.id.clone() + ".json"),
+    )?;
+    net::download_file_if_changed(
+        &asset_index.url,
+        &index_file,
+        Some(&asset_index.checksum()),
+        progress,
+    )
+    .await?;
+    let index = std::fs::read(&index_file).with_path(&index_file)?;
+    let index =
+        serde_json::from_slice::<AssetObjects>(&index).map_err(|e| InstallerError::Metadata {
+            message: format!("Invalid asset index {}", asset_index.id),
+            source: Some(Box::new(e)),
+        })?;
+    let objects_dir = assets_dir.join("objects");
+    let mut objects: Vec<Download> = Vec::new();
+    for object in index.objects.values() {
+        let file = safe_join(&objects_dir, &object.path()?)?;
+        if queued.insert(file.clone()) {
+            objects.push(Download {
+                url: object.url()?,
+                file,
+                checksum: Some(object.checksum()),
+            });
+        }
+    }
+    info!("Downloading {} asset objects", objects.len());
+    download_all(objects, None, progress).await
+}
+
+/// Downloads files concurrently, sending the given event for each completed one
+async fn download_all(
+    downloads: Vec<Download>,
+    completed: Option<ProgressEvent>,
+    progress: &Progress,
+) -> Result<(), InstallerError> {
+    let mut tasks = JoinSet::new();
+    for download in downloads {
+        let progress = progress.clone();
+        tasks.spawn(async move {
+            match &download.checksum {
+                Some(checksum) => {
+                    net::download_file_if_changed(
+                        &download.url,
+                        &download.file,
+                        Some(checksum),
+                        &progress,
+                    )
+                    .await
                 }
-                let profiles = raw_profiles.as_object_mut().unwrap();
-
-                let new_profile_name =
-                    "Ornithe (".to_owned() + loader_type.get_localized_name() + ") " + &version.id;
-
-                if profiles.contains_key(&new_profile_name) {
-                    let raw_profile = profiles.get_mut(&new_profile_name).unwrap();
-                    if !raw_profile.is_object() {
-                        return Err(InstallerError(format!(
-                            "Cannot update profile of name {new_profile_name} because it is not an object!"
-                        )));
-                    }
-
-                    raw_profile
-                        .as_object_mut()
-                        .unwrap()
-                        .insert("lastVersionId".to_string(), Value::String(name));
-                } else {
-                    let profile = json!({
-                        "name": new_profile_name,
-                        "type":"custom",
-                        "created": Utc::now(),
-                        "lastUsed": Utc::now(),
-                        "icon": get_icon_string(),
-                        "lastVersionId": name
-                    });
-                    profiles.insert(new_profile_name, profile);
+                None => {
+                    net::download_maven_artifact(&download.url, &download.file, &progress).await
                 }
+            }
+        });
+    }
 
-                std::fs::write(&launcher_profiles_path, serde_json::to_string(&json)?)?;
-
-                Ok(())
+    let mut failures = Vec::new();
+    while let Some(done) = tasks.join_next().await {
+        match done {
+            Ok(Ok(())) => {
+                if let Some(event) = &completed {
+                    progress.send(event.clone());
+                }
             }
-            Err(_) => Err(InstallerError(
-                "Failed to parse launcher_profiles.json json".to_string(),
-            )),
-        },
-        Err(_) => Err(InstallerError(
-            "Failed to read launcher_profiles.json".to_string(),
-        )),
+            Ok(Err(e)) => failures.push(e),
+            Err(e) => failures.push(e.into()),
+        }
     }
+
+    InstallerError::combine("Failed to download game files", failures)
 }
 
-fn get_icon_string() -> String {
-    let base64 = BASE64_STANDARD_NO_PAD.encode(crate::ORNITHE_ICON_BYTES);
+/// Sets up the directory of a vanilla version. A client jar matching the given
+/// download, e.g. from an earlier predownload, is kept. Otherwise the directory
+/// is cleared and gets an empty jar.
+fn create_vanilla_dir(
+    dir: &Path,
+    jar: &Path,
+    client: Option<&VersionDownload>,
+) -> Result<(), InstallerError> {
+    if client.is_some_and(|client| net::is_intact(&client.url, jar, &client.checksum())) {
+        info!("Keeping the client jar {}", jar.display());
+        return Ok(());
+    }
+    if std::fs::exists(dir).unwrap_or_default() {
+        std::fs::remove_dir_all(dir).with_path(dir)?;
+    }
+    std::fs::create_dir_all(dir).with_path(dir)?;
+    std::fs::File::create(jar).with_path(jar)?;
+    Ok(())
+}
+
+fn create_empty_jar(dir: &PathBuf, name: &String) -> Result<(), InstallerError> {
+    std::fs::create_dir_all(dir).with_path(dir)?;
+    let jar = dir.join(name.clone() + ".jar");
+    std::fs::File::create(&jar).with_path(&jar)?;
+    Ok(())
+}
+
+/// Points the profile of the given name to a version in every profile file,
+/// creating the profile where it does not exist yet. The given fields are
+/// set on the profile in either case.
+fn update_profiles(
+    game_dir: &Path,
+    profile_name: &str,
+    version_name: &str,
+    fields: &Map<String, Value>,
+) -> Result<Vec<PathBuf>, InstallerError> {
+    launcher_profiles::edit_all(game_dir, true, |profiles| {
+        let raw_profile = profiles.entry(profile_name).or_insert_with(|| {
+            json!({
+                "name": profile_name,
+                "type":"custom",
+                "created": Utc::now(),
+                "lastUsed": Utc::now(),
+                "icon": get_icon_string(crate::ORNITHE_ICON_BYTES),
+            })
+        });
+        let Some(profile) = raw_profile.as_object_mut() else {
+            return Err(InstallerError::UserInput(format!(
+                "Cannot update profile of name {profile_name} because it is not an object!"
+            )));
+        };
+        profile.insert(
+            "lastVersionId".to_string(),
+            Value::String(version_name.to_owned()),
+        );
+        profile.extend(fields.clone());
+        Ok(true)
+    })
+}
+
+/// Reads a PNG file as a launcher profile icon
+fn read_icon(path: &Path) -> Result<String, InstallerError> {
+    const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    let bytes = std::fs::read(path).with_path(path)?;
+    if !bytes.starts_with(PNG_SIGNATURE) {
+        return Err(InstallerError::UserInput(format!(
+            "{} is not a PNG image",
+            path.display()
+        )));
+    }
+    Ok(get_icon_string(&bytes))
+}
+
+fn get_icon_string(png: &[u8]) -> String {
+    let base64 = BASE64_STANDARD_NO_PAD.encode(png);
     "data:image/png;base64,".to_string() + &base64
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Map;
+    use sha1::{Digest, Sha1};
+
+    use crate::{
+        actions::test_fixtures::{files, fixture, read},
+        net::manifest::VersionDownload,
+    };
+
+    use super::{
+        ProfileOptions, create_vanilla_dir, is_downgrade, parse_version_name,
+        read_profile_versions, remove_profiles, repoint_profiles, uninstall, update_profiles,
+    };
+
+    const PROFILE: &str = "Ornithe (Fabric) 1.8.9";
+    const OLD_VERSION: &str = "fabric-loader-0.16.0-1.8.9-ornithe";
+    const NEW_VERSION: &str = "fabric-loader-0.16.10-1.8.9-ornithe";
+
+    fn parse(name: &str, vanilla: &[&str]) -> Option<(String, String, String)> {
+        let vanilla = vanilla.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        parse_version_name(name.to_owned(), &vanilla).map(|version| {
+            (
+                version.loader_name,
+                version.loader_version,
+                version.minecraft_version,
+            )
+        })
+    }
+
+    #[test]
+    fn plain_install_keeps_a_predownloaded_client_jar() {
+        let dir = tempfile::tempdir().unwrap();
+        let version_dir = dir.path().join("1.8.9-vanilla");
+        let jar = version_dir.join("1.8.9-vanilla.jar");
+        std::fs::create_dir_all(&version_dir).unwrap();
+        std::fs::write(&jar, b"client jar").unwrap();
+        let mut client = VersionDownload {
+            sha1: format!("{:x}", Sha1::digest(b"client jar")),
+            size: 10,
+            url: "https://piston-data.mojang.com/v1/objects/client.jar".to_owned(),
+        };
+
+        create_vanilla_dir(&version_dir, &jar, Some(&client)).unwrap();
+        assert_eq!(std::fs::read(&jar).unwrap(), b"client jar");
+
+        // A jar that does not match is replaced by the empty stub
+        client.sha1 = format!("{:x}", Sha1::digest(b"other jar"));
+        create_vanilla_dir(&version_dir, &jar, Some(&client)).unwrap();
+        assert_eq!(std::fs::read(&jar).unwrap(), b"");
+        create_vanilla_dir(&version_dir, &jar, None).unwrap();
+        assert_eq!(std::fs::read(&jar).unwrap(), b"");
+    }
+
+    #[test]
+    fn detects_downgrades() {
+        assert!(is_downgrade("0.17.0", "0.16.10", "latest-stable"));
+        assert!(is_downgrade("0.16.10", "0.16.9", ">=0.16, <0.17"));
+        assert!(!is_downgrade("0.16.10", "0.16.9", "0.16.9"));
+        assert!(!is_downgrade("0.16.9", "0.16.10", "latest"));
+        assert!(!is_downgrade("custom", "0.16.10", "latest"));
+    }
+
+    #[test]
+    fn parses_version_names() {
+        let parsed = |loader: &str, loader_version: &str, minecraft_version: &str| {
+            Some((
+                loader.to_owned(),
+                loader_version.to_owned(),
+                minecraft_version.to_owned(),
+            ))
+        };
+        assert_eq!(
+            parse(OLD_VERSION, &["1.8.9", "1.7.10"]),
+            parsed("fabric", "0.16.0", "1.8.9")
+        );
+        // Dashes on both sides, the longest matching game version wins
+        assert_eq!(
+            parse(
+                "quilt-loader-0.17.0-beta.1-1.0.0-rc2-1633-ornithe",
+                &["1633", "rc2-1633", "1.0.0-rc2-1633"]
+            ),
+            parsed("quilt", "0.17.0-beta.1", "1.0.0-rc2-1633")
+        );
+        assert_eq!(
+            parse(
+                "fabric-loader-0.16.10-1.14-pre1-ornithe",
+                &["1.14", "1.14-pre1"]
+            ),
+            parsed("fabric", "0.16.10", "1.14-pre1")
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_version_names() {
+        // No vanilla stub for the game version
+        assert_eq!(parse(OLD_VERSION, &["1.7.10"]), None);
+        // No loader version left
+        assert_eq!(parse("fabric-loader-1.8.9-ornithe", &["1.8.9"]), None);
+        assert_eq!(parse("fabric-loader-0.16.0-1.8.9", &["1.8.9"]), None);
+        assert_eq!(parse("1.8.9-ornithe", &["1.8.9"]), None);
+    }
+
+    #[test]
+    fn uninstall_keeps_versions_if_profiles_cannot_be_edited() {
+        let dir = tempfile::tempdir().unwrap();
+        let versions = dir.path().join("versions");
+        for name in [OLD_VERSION, "1.8.9-vanilla"] {
+            std::fs::create_dir_all(versions.join(name)).unwrap();
+        }
+        std::fs::write(dir.path().join("launcher_profiles.json"), "{").unwrap();
+
+        assert!(uninstall(dir.path(), Some("1.8.9"), None).is_err());
+        assert_eq!(files(&versions), ["1.8.9-vanilla", OLD_VERSION]);
+
+        std::fs::write(
+            dir.path().join("launcher_profiles.json"),
+            r#"{ "profiles": { "Ornithe": { "lastVersionId": "fabric-loader-0.16.0-1.8.9-ornithe" } } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            uninstall(dir.path(), Some("1.8.9"), None).unwrap(),
+            [OLD_VERSION]
+        );
+        assert!(files(&versions).is_empty());
+        let json = read(dir.path(), "launcher_profiles.json");
+        assert!(json["profiles"].as_object().unwrap().is_empty());
+    }
+
+    #[test]
+    fn update_profiles_updates_every_file() {
+        let dir = fixture("both");
+        let updated = update_profiles(dir.path(), PROFILE, NEW_VERSION, &Map::new()).unwrap();
+        assert_eq!(updated.len(), 2);
+
+        for name in [
+            "launcher_profiles.json",
+            "launcher_profiles_microsoft_store.json",
+        ] {
+            let json = read(dir.path(), name);
+            let profiles = json["profiles"].as_object().unwrap();
+            assert_eq!(profiles.len(), 2, "{name}");
+            assert_eq!(profiles[PROFILE]["lastVersionId"], NEW_VERSION, "{name}");
+            // Fields other than the version are left alone
+            assert_eq!(profiles[PROFILE]["icon"], "Furnace", "{name}");
+        }
+        assert_eq!(
+            read(dir.path(), "launcher_profiles.json")["settings"]["enableSnapshots"],
+            false
+        );
+
+        let files = files(dir.path());
+        assert_eq!(files.iter().filter(|f| f.ends_with(".bak")).count(), 2);
+        assert!(!files.iter().any(|f| f.ends_with(".tmp")));
+    }
+
+    #[test]
+    fn update_profiles_only_uses_existing_store_file() {
+        let dir = fixture("store-only");
+        let updated = update_profiles(dir.path(), PROFILE, NEW_VERSION, &Map::new()).unwrap();
+        assert_eq!(
+            updated,
+            vec![dir.path().join("launcher_profiles_microsoft_store.json")]
+        );
+        assert!(!dir.path().join("launcher_profiles.json").exists());
+
+        let json = read(dir.path(), "launcher_profiles_microsoft_store.json");
+        assert_eq!(json["profiles"][PROFILE]["lastVersionId"], NEW_VERSION);
+        assert_eq!(json["profiles"][PROFILE]["type"], "custom");
+    }
+
+    #[test]
+    fn update_profiles_creates_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let updated = update_profiles(dir.path(), PROFILE, NEW_VERSION, &Map::new()).unwrap();
+        assert_eq!(updated, vec![dir.path().join("launcher_profiles.json")]);
+
+        let json = read(dir.path(), "launcher_profiles.json");
+        assert_eq!(json["profiles"][PROFILE]["lastVersionId"], NEW_VERSION);
+        assert_eq!(files(dir.path()), vec!["launcher_profiles.json"]);
+    }
+
+    #[test]
+    fn failed_update_leaves_every_file_untouched() {
+        let dir = fixture("invalid-profile");
+        let before = files(dir.path())
+            .into_iter()
+            .map(|name| std::fs::read(dir.path().join(name)).unwrap())
+            .collect::<Vec<_>>();
+
+        assert!(update_profiles(dir.path(), PROFILE, NEW_VERSION, &Map::new()).is_err());
+
+        let after = files(dir.path())
+            .into_iter()
+            .map(|name| std::fs::read(dir.path().join(name)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn update_profiles_applies_options() {
+        let dir = fixture("both");
+        let game_dir = dir.path().join("modpack");
+        let icon = dir.path().join("icon.png");
+        std::fs::write(&icon, crate::ORNITHE_ICON_BYTES).unwrap();
+        let options = ProfileOptions {
+            name: None,
+            icon: Some(icon),
+            java_args: Some("-Xmx4G".to_owned()),
+            java_dir: Some(dir.path().join("java")),
+            resolution: Some((1280, 720)),
+            game_dir: Some(game_dir.clone()),
+        };
+        update_profiles(dir.path(), PROFILE, NEW_VERSION, &options.fields().unwrap()).unwrap();
+
+        let json = read(dir.path(), "launcher_profiles_microsoft_store.json");
+        let profile = &json["profiles"][PROFILE];
+        assert_eq!(profile["lastVersionId"], NEW_VERSION);
+        assert!(
+            profile["icon"]
+                .as_str()
+                .unwrap()
+                .starts_with("data:image/png;base64,")
+        );
+        assert_eq!(profile["javaArgs"], "-Xmx4G");
+        assert_eq!(profile["resolution"]["width"], 1280);
+        assert_eq!(profile["resolution"]["height"], 720);
+        assert_eq!(profile["gameDir"], game_dir.to_string_lossy().as_ref());
+        // Validating the options has no side effects
+        assert!(!game_dir.exists());
+
+        let not_png = ProfileOptions {
+            icon: Some(dir.path().join("launcher_profiles.json")),
+            ..Default::default()
+        };
+        assert!(not_png.fields().is_err());
+        let file_as_game_dir = ProfileOptions {
+            game_dir: Some(dir.path().join("launcher_profiles.json")),
+            ..Default::default()
+        };
+        assert!(file_as_game_dir.fields().is_err());
+    }
+
+    #[test]
+    fn repoint_and_remove_profiles_in_every_file() {
+        let dir = fixture("both");
+        let repointed = repoint_profiles(dir.path(), OLD_VERSION, NEW_VERSION).unwrap();
+        assert_eq!(repointed.len(), 2);
+        assert_eq!(
+            read_profile_versions(dir.path()).unwrap(),
+            vec![
+                ("latest-release".to_owned(), "".to_owned()),
+                (NEW_VERSION.to_owned(), PROFILE.to_owned()),
+            ]
+        );
+
+        let removed = remove_profiles(dir.path(), &[NEW_VERSION.to_owned()]).unwrap();
+        assert_eq!(removed.len(), 2);
+        for name in [
+            "launcher_profiles.json",
+            "launcher_profiles_microsoft_store.json",
+        ] {
+            let json = read(dir.path(), name);
+            assert!(json["profiles"].get(PROFILE).is_none(), "{name}");
+            assert_eq!(json["profiles"].as_object().unwrap().len(), 1, "{name}");
+        }
+
+        // Nothing is written when no profile matches
+        assert!(
+            remove_profiles(dir.path(), &[OLD_VERSION.to_owned()])
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn reading_versions_does_not_restore_corrupt_files() {
+        let dir = fixture("corrupt");
+        let before = files(dir.path());
+        assert_eq!(
+            read_profile_versions(dir.path()).unwrap(),
+            vec![(OLD_VERSION.to_owned(), PROFILE.to_owned())]
+        );
+        assert_eq!(files(dir.path()), before);
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use semver::Version;
+
+use crate::{
+    errors::{InstallerError, PathContext},
+    net::meta::{LoaderType, LoaderVersion, select_loader_version},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InstallKind {
+    Client,
+    Instance,
+    Server,
+}
+
+impl Display for InstallKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            InstallKind::Client => "Client",
+            InstallKind::Instance => "MultiMC/Prism instance",
+            InstallKind::Server => "Server",
+        })
+    }
+}
+
+/// An Ornithe installation found on disk
+pub struct Installation {
+    pub kind: InstallKind,
+    pub path: PathBuf,
+    pub minecraft_version: String,
+    pub loader_name: String,
+    /// Empty if it could not be determined
+    pub loader_version: String,
+    /// Names of the launcher profiles using this installation
+    pub profiles: Vec<String>,
+}
+
+impl Installation {
+    /// Whether the installed loader is older than the given version
+    pub fn is_outdated(&self, latest: &LoaderVersion) -> bool {
+        match (Version::parse(&self.loader_version), latest.semver()) {
+            (Ok(installed), Some(latest)) => installed < latest,
+            _ => false,
+        }
+    }
+}
+
+/// Scans directories for launcher versions, MultiMC/Prism instances and servers
+/// installed by Ornithe. Instances and servers are also looked for in the
+/// subdirectories of each directory and of its `instances` directory.
+pub fn scan(dirs: &[PathBuf]) -> Result<Vec<Installation>, InstallerError> {
+    let mut out = Vec::new();
+    for dir in dirs {
+        if !dir.is_dir() {
+            warn!("{} is not a directory", dir.display());
+            continue;
+        }
+        match super::client::find_installations(dir) {
+            Ok(installations) => out.extend(installations),
+            Err(e) => warn!("Failed to scan {}: {}", dir.display(), e.report()),
+        }
+        for candidate in candidates(dir)? {
+            match super::mmc_pack::find_installation(&candidate) {
+                Ok(installation) => out.extend(installation),
+                Err(e) => warn!("Failed to read {}: {}", candidate.display(), e.report()),
+            }
+            match super::server::find_installations(&candidate) {
+                Ok(installations) => out.extend(installations),
+                Err(e) => warn!("Failed to read {}: {}", candidate.display(), e.report()),
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn candidates(dir: &Path) -> Result<Vec<PathBuf>, InstallerError> {
+    let mut out = vec![dir.to_path_buf()];
+    for parent in [dir.to_path_buf(), dir.join("instances")] {
+        if !parent.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(&parent).with_path(&parent)? {
+            let path = entry.with_path(&parent)?.path();
+            if path.is_dir() {
+                out.push(path);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Fetches the latest stable version of each loader used by the installations.
+/// Loaders whose versions cannot be fetched are left out.
+pub async fn latest_stable_versions(
+    installations: &[Installation],
+) -> HashMap<String, LoaderVersion> {
+    let loader_names = installations
+        .iter()
+        .map(|installation| installation.loader_name.as_str())
+        .collect::<HashSet<_>>();
+    let mut out = HashMap::new();
+    for name in loader_names {
+        let Some(loader_type) = LoaderType::find(name) else {
+            continue;
+        };
+        match crate::net::meta::fetch_loader_versions_type(&loader_type).await {
+            Ok(versions) => {
+                if let Ok(latest) = select_loader_version(&versions, "latest-stable") {
+                    out.insert(name.to_owned(), latest.clone());
+                }
+            }
+            Err(e) => warn!(
+                "Failed to fetch {} Loader versions: {}",
+                loader_type.get_localized_name(),
+                e.report()
+            ),
+        }
+    }
+    out
+}
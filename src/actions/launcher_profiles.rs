@@ -0,0 +1,355 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use log::{info, warn};
+use serde_json::{Map, Value, json};
+
+use crate::errors::{InstallerError, PathContext};
+
+/// Profile files of the official launcher, the first being created if none exist.
+/// Newer launchers use the second instead of or in addition to the first.
+pub const FILE_NAMES: [&str; 2] = [
+    "launcher_profiles.json",
+    "launcher_profiles_microsoft_store.json",
+];
+
+/// How many backups of a profiles file are kept
+const MAX_BACKUPS: usize = 5;
+
+/// A profiles file of the official launcher. Edits are written to a temporary
+/// file that replaces the original only once complete, after backing it up.
+pub struct LauncherProfiles {
+    path: PathBuf,
+    json: Value,
+    /// The backup read instead of the file on disk because that was corrupt
+    restored_from: Option<PathBuf>,
+}
+
+impl LauncherProfiles {
+    /// Reads every profiles file present in the given launcher directory
+    pub fn open_existing(game_dir: &Path) -> Result<Vec<LauncherProfiles>, InstallerError> {
+        FILE_NAMES
+            .iter()
+            .map(|name| game_dir.join(name))
+            .filter(|path| path.exists())
+            .map(LauncherProfiles::open)
+            .collect()
+    }
+
+    /// Reads every profiles file present in the given launcher
+    /// directory, or starts a new one if there are none
+    pub fn open_all(game_dir: &Path) -> Result<Vec<LauncherProfiles>, InstallerError> {
+        let existing = LauncherProfiles::open_existing(game_dir)?;
+        if existing.is_empty() {
+            return Ok(vec![LauncherProfiles::open(game_dir.join(FILE_NAMES[0]))?]);
+        }
+        Ok(existing)
+    }
+
+    /// Reads a profiles file. A missing file is treated as empty, a corrupt one
+    /// is read from its most recent valid backup. Nothing is written until the
+    /// file is saved, which then also moves the corrupt file aside.
+    fn open(path: PathBuf) -> Result<LauncherProfiles, InstallerError> {
+        if !path.exists() {
+            return Ok(LauncherProfiles {
+                path,
+                json: json!({
+                    "profiles": {},
+                    "settings": {},
+                    "version": 3
+                }),
+                restored_from: None,
+            });
+        }
+
+        let content = std::fs::read_to_string(&path).with_path(&path)?;
+        let error = match parse(&content) {
+            Ok(json) => {
+                return Ok(LauncherProfiles {
+                    path,
+                    json,
+                    restored_from: None,
+                });
+            }
+            Err(e) => e,
+        };
+        for backup in backups(&path)?.iter().rev() {
+            let Ok(content) = std::fs::read_to_string(backup) else {
+                continue;
+            };
+            if let Ok(json) = parse(&content) {
+                warn!(
+                    "{} is corrupt ({}), using {} instead",
+                    path.display(),
+                    error,
+                    backup.display()
+                );
+                return Ok(LauncherProfiles {
+                    path,
+                    json,
+                    restored_from: Some(backup.clone()),
+                });
+            }
+        }
+        Err(InstallerError::UserInput(format!(
+            "Failed to parse {} and no valid backup is available: {}",
+            path.display(),
+            error
+        )))
+    }
+
+    pub fn profiles(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.json["profiles"].as_object().into_iter().flatten()
+    }
+
+    pub fn profiles_mut(&mut self) -> &mut Map<String, Value> {
+        self.json["profiles"]
+            .as_object_mut()
+            .expect("profiles are validated when reading")
+    }
+
+    /// Backs up the current file and atomically replaces it with the edited profiles.
+    /// A corrupt file is moved aside instead of being backed up.
+    pub fn save(&self) -> Result<(), InstallerError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).with_path(parent)?;
+        }
+        if let Some(backup) = &self.restored_from
+            && self.path.exists()
+        {
+            // Keep the corrupt file around, it may still contain something of value
+            let corrupt = sibling(&self.path, "corrupt");
+            std::fs::rename(&self.path, &corrupt).with_path(&corrupt)?;
+            warn!(
+                "Restored {} from {} and moved the corrupt file to {}",
+                self.path.display(),
+                backup.display(),
+                corrupt.display()
+            );
+        } else if self.path.exists() {
+            let backup = sibling(&self.path, "bak");
+            std::fs::copy(&self.path, &backup).with_path(&backup)?;
+            info!("Backed up {} to {}", self.path.display(), backup.display());
+            prune_backups(&self.path);
+        }
+
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let mut file = std::fs::File::create(&temp).with_path(&temp)?;
+        file.write_all(serde_json::to_string_pretty(&self.json)?.as_bytes())
+            .with_path(&temp)?;
+        file.sync_all().with_path(&temp)?;
+        drop(file);
+        std::fs::rename(&temp, &self.path).with_path(&self.path)
+    }
+}
+
+/// Applies an edit to every profiles file of a launcher directory, optionally starting a
+/// new file if there are none. All files are edited before any of them is written, so
+/// a failing edit leaves every file untouched. The edit returns whether it changed
+/// anything, and the paths of the files that were changed are returned. Files that
+/// were restored from a backup are written even if the edit left them unchanged.
+pub fn edit_all<F>(
+    game_dir: &Path,
+    create: bool,
+    mut edit: F,
+) -> Result<Vec<PathBuf>, InstallerError>
+where
+    F: FnMut(&mut Map<String, Value>) -> Result<bool, InstallerError>,
+{
+    let files = if create {
+        LauncherProfiles::open_all(game_dir)?
+    } else {
+        LauncherProfiles::open_existing(game_dir)?
+    };
+    let mut changed = Vec::new();
+    for mut file in files {
+        if edit(file.profiles_mut())? || file.restored_from.is_some() {
+            changed.push(file);
+        }
+    }
+    for file in &changed {
+        file.save()?;
+        info!("Updated {}", file.path.display());
+    }
+    Ok(changed.into_iter().map(|file| file.path).collect())
+}
+
+/// A file next to the given one, named after it with a timestamp and extension
+fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(
+        "{}.{}.{}",
+        name,
+        Utc::now().format("%Y%m%d%H%M%S%3f"),
+        extension
+    ))
+}
+
+fn parse(content: &str) -> Result<Value, String> {
+    let mut json = serde_json::from_str::<Value>(content).map_err(|e| e.to_string())?;
+    let object = json
+        .as_object_mut()
+        .ok_or("the file does not contain an object")?;
+    match object
+        .entry("profiles")
+        .or_insert_with(|| Value::Object(Map::new()))
+    {
+        Value::Object(_) => Ok(json),
+        _ => Err("\"profiles\" field must be an object".to_owned()),
+    }
+}
+
+/// Backups of a profiles file, oldest first
+fn backups(path: &Path) -> Result<Vec<PathBuf>, InstallerError> {
+    let Some(dir) = path.parent() else {
+        return Ok(Vec::new());
+    };
+    let prefix = path.file_name().unwrap_or_default().to_string_lossy() + ".";
+    let prefix = prefix.as_ref();
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(dir).with_path(dir)? {
+        let entry = entry.with_path(dir)?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with(prefix) && name.ends_with(".bak") {
+            backups.push(entry.path());
+        }
+    }
+    // The timestamps in the names sort chronologically
+    backups.sort();
+    Ok(backups)
+}
+
+fn prune_backups(path: &Path) {
+    let backups = match backups(path) {
+        Ok(backups) => backups,
+        Err(e) => {
+            warn!(
+                "Failed to list backups of {}: {}",
+                path.display(),
+                e.report()
+            );
+            return;
+        }
+    };
+    for backup in backups.iter().rev().skip(MAX_BACKUPS) {
+        if let Err(e) = std::fs::remove_file(backup) {
+            warn!("Failed to remove old backup {}: {}", backup.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::json;
+
+    use super::{LauncherProfiles, MAX_BACKUPS, edit_all};
+    use crate::actions::test_fixtures::{files, fixture, read};
+
+    const PROFILE: &str = "Ornithe (Fabric) 1.8.9";
+    const VERSION: &str = "fabric-loader-0.16.0-1.8.9-ornithe";
+
+    fn contents(dir: &Path) -> Vec<(String, Vec<u8>)> {
+        files(dir)
+            .into_iter()
+            .map(|name| {
+                let content = std::fs::read(dir.join(&name)).unwrap();
+                (name, content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reading_a_corrupt_file_uses_the_backup_without_writing() {
+        let dir = fixture("corrupt");
+        let before = contents(dir.path());
+        let files = LauncherProfiles::open_existing(dir.path()).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].json["profiles"][PROFILE]["lastVersionId"], VERSION);
+        assert_eq!(contents(dir.path()), before);
+    }
+
+    #[test]
+    fn editing_a_corrupt_file_restores_the_backup() {
+        let dir = fixture("corrupt");
+        let changed = edit_all(dir.path(), false, |_| Ok(false)).unwrap();
+        assert_eq!(changed, vec![dir.path().join("launcher_profiles.json")]);
+        assert_eq!(
+            read(dir.path(), "launcher_profiles.json")["profiles"][PROFILE]["lastVersionId"],
+            VERSION
+        );
+        let files = files(dir.path());
+        assert_eq!(
+            files
+                .iter()
+                .filter(|name| name.ends_with(".corrupt"))
+                .count(),
+            1
+        );
+        assert_eq!(
+            files.iter().filter(|name| name.ends_with(".bak")).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn corrupt_file_without_backup_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("launcher_profiles.json"), "{").unwrap();
+        assert!(edit_all(dir.path(), true, |_| Ok(true)).is_err());
+        assert_eq!(files(dir.path()), vec!["launcher_profiles.json"]);
+    }
+
+    #[test]
+    fn saving_backs_up_and_replaces_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("launcher_profiles.json");
+        std::fs::write(&path, r#"{"profiles": {}, "version": 3}"#).unwrap();
+        edit_all(dir.path(), false, |profiles| {
+            profiles.insert(PROFILE.to_owned(), json!({ "lastVersionId": VERSION }));
+            Ok(true)
+        })
+        .unwrap();
+
+        assert_eq!(
+            read(dir.path(), "launcher_profiles.json")["profiles"][PROFILE]["lastVersionId"],
+            VERSION
+        );
+        let files = files(dir.path());
+        assert_eq!(files.len(), 2, "{:?}", files);
+        assert!(files[1].ends_with(".bak"));
+        assert_eq!(read(dir.path(), &files[1])["profiles"], json!({}));
+        assert!(!files.iter().any(|name| name.ends_with(".tmp")));
+    }
+
+    #[test]
+    fn old_backups_are_pruned() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("launcher_profiles.json"), "{}").unwrap();
+        for i in 0..MAX_BACKUPS + 2 {
+            std::fs::write(
+                dir.path()
+                    .join(format!("launcher_profiles.json.2024030100000000{}.bak", i)),
+                "{}",
+            )
+            .unwrap();
+        }
+        edit_all(dir.path(), false, |_| Ok(true)).unwrap();
+
+        let backups = files(dir.path())
+            .into_iter()
+            .filter(|name| name.ends_with(".bak"))
+            .collect::<Vec<_>>();
+        assert_eq!(backups.len(), MAX_BACKUPS);
+        // The oldest ones go first
+        assert!(!backups.iter().any(|name| name.ends_with("0000000000.bak")));
+        assert!(!backups.iter().any(|name| name.ends_with("0000000001.bak")));
+    }
+}
@@ -1,17 +1,25 @@
-use std::{fs::File, io::Write, path::PathBuf};
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
-use log::info;
 use serde_json::{Value, json};
 use zip::{ZipWriter, write::SimpleFileOptions};
 
 use crate::{
-    errors::InstallerError,
+    errors::{InstallerError, PathContext},
     net::{
+        library::MavenCoordinate,
         manifest::{self, MinecraftVersion},
         meta::{self, LoaderType, LoaderVersion},
     },
+    progress::Progress,
+    security::safe_join,
 };
 
+use super::installed::{InstallKind, Installation};
+
 const INTERMEDIARY_PATCH: &str =
     include_str!("../../res/packformat/patches/net.fabricmc.intermediary.json");
 const INSTANCE_CONFIG: &str = include_str!("../../res/packformat/instance.cfg");
@@ -24,33 +32,31 @@ pub async fn install(
     output_dir: PathBuf,
     copy_profile_path: bool,
     generate_zip: bool,
+    progress: Progress,
 ) -> Result<(), InstallerError> {
     if !output_dir.exists() {
-        std::fs::create_dir_all(&output_dir)?;
+        std::fs::create_dir_all(&output_dir).with_path(&output_dir)?;
     }
-    let output_dir = output_dir.canonicalize()?;
+    let output_dir = output_dir.canonicalize().with_path(&output_dir)?;
 
-    info!("Fetching version information...");
+    progress.phase("Fetching version information...");
     let version_id = version.get_id(&crate::net::GameSide::Client).await?;
     let intermediary_versions = meta::fetch_intermediary_versions().await?;
-    let intermediary_version = intermediary_versions
-        .get(&version_id)
-        .ok_or(InstallerError(
-            "Could not find matching intermediary version".to_owned(),
-        ))?;
+    let intermediary_version = intermediary_versions.get(&version_id).ok_or_else(|| {
+        InstallerError::NotFound(format!(
+            "Could not find matching intermediary version for {}",
+            version_id
+        ))
+    })?;
 
     let intermediary_maven = intermediary_version
         .maven
-        .clone()
-        .strip_suffix(&(":".to_owned() + &intermediary_version.version))
-        .ok_or(InstallerError(
-            "Failed to retrieve intermediary maven coordinates".to_owned(),
-        ))?
-        .to_owned();
+        .parse::<MavenCoordinate>()?
+        .module();
 
     let lwjgl_version = manifest::find_lwjgl_version(&version).await?;
 
-    info!("Transforming templates...");
+    progress.phase("Transforming templates...");
 
     let mut transformed_pack_json = serde_json::from_str::<Value>(
         &transform_pack_json(
@@ -70,18 +76,18 @@ pub async fn install(
     let minecraft_patch_json = get_mmc_launch_json(&version, &lwjgl_version).await?;
 
     let output_file = if generate_zip {
-        output_dir.join("Ornithe-".to_owned() + &version.id + ".zip")
+        safe_join(&output_dir, &("Ornithe-".to_owned() + &version.id + ".zip"))?
     } else {
-        output_dir.join("Ornithe-".to_owned() + &version.id)
+        safe_join(&output_dir, &("Ornithe-".to_owned() + &version.id))?
     };
 
-    info!("Fetching library information...");
+    progress.phase("Fetching library information...");
 
     let extra_libs =
         meta::fetch_profile_libraries(&intermediary_version, &loader_type, &loader_version).await?;
 
     let mut zip: Box<dyn Writer> = if generate_zip {
-        info!("Generating instance zip...");
+        progress.phase("Generating instance zip...");
 
         if std::fs::exists(&output_file).unwrap_or_default() {
             std::fs::remove_file(&output_file)?;
@@ -89,7 +95,7 @@ pub async fn install(
         let file = std::fs::File::create_new(&output_file)?;
         Box::new(ZipWriter::new(file))
     } else {
-        info!("Generating output files...");
+        progress.phase("Generating output files...");
 
         Box::new(output_file.clone())
     };
@@ -118,21 +124,25 @@ pub async fn install(
 
     let pack_components = transformed_pack_json["components"].as_array_mut().unwrap();
     for library in extra_libs {
-        let colons = library
-            .name
-            .char_indices()
-            .filter(|c| c.1 == ':')
-            .map(|c| c.0);
-        let index = colons.clone().last().unwrap();
-        let uid = library.name.get(0..index).unwrap().replace(":", ".");
-        let lib_name = library
-            .name
-            .get((colons.clone().next().unwrap() + 1)..colons.clone().last().unwrap())
-            .unwrap();
-        let version = library.name.get(0..(colons.last().unwrap() + 1)).unwrap();
-        zip.write_file(&("patches/".to_owned() + &uid + ".json"), 
-            format!(r#"{{"formatVersion": 1, "libraries": [{{"name": "{}","url": "{}"}}], "name": "{}", "type": "release", "uid": "{}", "version": "{}"}}"#,
-             library.name, library.url, lib_name, uid, version).as_bytes())?;
+        let coordinate = library.coordinate()?;
+        let uid = coordinate.prism_uid();
+        let lib_name = &coordinate.artifact;
+        let version = &coordinate.version;
+        let patch = json!({
+            "formatVersion": 1,
+            "libraries": [{
+                "name": library.name,
+                "url": library.url,
+            }],
+            "name": lib_name,
+            "type": "release",
+            "uid": uid,
+            "version": version
+        });
+        zip.write_file(
+            &("patches/".to_owned() + &uid + ".json"),
+            &serde_json::to_vec(&patch)?,
+        )?;
 
         pack_components.push(json!({
             "cachedName": lib_name,
@@ -148,14 +158,52 @@ pub async fn install(
 
     if copy_profile_path {
         cli_clipboard::set_contents(output_file.to_string_lossy().into_owned())
-            .map_err(|_| InstallerError("Failed to copy profile path".to_owned()))?;
+            .map_err(|e| InstallerError::other(format!("Failed to copy profile path: {}", e)))?;
     }
 
-    info!("Done!");
+    progress.phase("Done!");
 
     Ok(())
 }
 
+/// Reads an instance directory, returning the Ornithe installation in it, if any.
+/// Instances are recognized by the intermediary patch the installer adds.
+pub fn find_installation(dir: &Path) -> Result<Option<Installation>, InstallerError> {
+    let pack = dir.join("mmc-pack.json");
+    if !pack.is_file() || !dir.join("patches/net.fabricmc.intermediary.json").is_file() {
+        return Ok(None);
+    }
+    let pack_json = serde_json::from_str::<Value>(
+        &std::fs::read_to_string(&pack).with_path(&pack)?,
+    )
+    .map_err(|e| InstallerError::UserInput(format!("Failed to parse {}: {}", pack.display(), e)))?;
+    let components = pack_json["components"]
+        .as_array()
+        .ok_or_else(|| InstallerError::metadata(format!("{} has no components", pack.display())))?;
+    let component_version = |uid: &str| {
+        components
+            .iter()
+            .find(|component| component["uid"] == uid)
+            .and_then(|component| component["version"].as_str())
+            .map(str::to_owned)
+    };
+
+    let Some(minecraft_version) = component_version("net.minecraft") else {
+        return Ok(None);
+    };
+    Ok(LoaderType::all().iter().find_map(|loader_type| {
+        let loader_version = component_version(&loader_type.get_maven_uid())?;
+        Some(Installation {
+            kind: InstallKind::Instance,
+            path: dir.to_path_buf(),
+            minecraft_version: minecraft_version.clone(),
+            loader_name: loader_type.get_name().to_owned(),
+            loader_version,
+            profiles: Vec::new(),
+        })
+    }))
+}
+
 async fn transform_intermediary_patch(
     version: &MinecraftVersion,
     intermediary_version: &String,
@@ -183,7 +231,7 @@ async fn transform_pack_json(
             "${loader_name}",
             &(loader_type.get_localized_name().to_owned() + " Loader"),
         )
-        .replace("${loader_uid}", loader_type.get_maven_uid())
+        .replace("${loader_uid}", &loader_type.get_maven_uid())
         .replace("${lwjgl_version}", &lwjgl_version)
         .replace("${lwjgl_major_ver}", &lwjgl_major.to_string())
         .replace(
@@ -201,9 +249,14 @@ async fn get_mmc_launch_json(
     lwjgl_version: &String,
 ) -> Result<String, InstallerError> {
     let client_name = format!("com.mojang:minecraft:{}:client", version.id);
-    let vanilla_json = serde_json::from_str::<Value>(&manifest::fetch_launch_json(version).await?)?;
+    let vanilla_json = manifest::fetch_launch_json(version).await?;
 
-    let client = vanilla_json["downloads"]["client"].as_object().unwrap();
+    let client = vanilla_json.downloads.get("client").ok_or_else(|| {
+        InstallerError::metadata(format!(
+            "Launch json for Minecraft {} has no client download",
+            version.id
+        ))
+    })?;
 
     let main_jar = json!({
         "downloads": {
@@ -212,52 +265,44 @@ async fn get_mmc_launch_json(
         "name": client_name
     });
 
-    let mut libraries = vanilla_json["libraries"].clone();
-    let vanilla_libraries = libraries.as_array_mut().unwrap();
-    vanilla_libraries.retain(|lib| {
-        let name = lib["name"].as_str().unwrap_or_default();
-        !name.contains("org.ow2.asm") && !name.contains("org.lwjgl")
-    });
+    let vanilla_libraries = vanilla_json
+        .libraries
+        .iter()
+        .filter(|lib| !lib.name.contains("org.ow2.asm") && !lib.name.contains("org.lwjgl"))
+        .collect::<Vec<_>>();
 
     let mut traits = Vec::new();
 
-    if vanilla_json["mainClass"]
-        .as_str()
-        .unwrap_or_default()
-        .contains("launchwrapper")
-    {
+    if vanilla_json.main_class.contains("launchwrapper") {
         traits.push("texturepacks");
     }
 
-    let mut minecraft_arguments = vanilla_json["minecraftArguments"]
-        .as_str()
-        .unwrap_or("")
-        .to_owned();
-    if let Some(game_arguments) = vanilla_json["arguments"]["game"].as_array() {
-        if !game_arguments.is_empty() {
-            let mut combined = String::new();
-            for arg in game_arguments {
-                if arg.is_string() {
-                    combined += &(arg.as_str().unwrap().to_owned() + " ");
-                }
+    let mut minecraft_arguments = vanilla_json.minecraft_arguments.clone().unwrap_or_default();
+    if let Some(arguments) = &vanilla_json.arguments
+        && !arguments.game.is_empty()
+    {
+        let mut combined = String::new();
+        for arg in &arguments.game {
+            if let Some(arg) = arg.as_str() {
+                combined += &(arg.to_owned() + " ");
             }
-            minecraft_arguments = combined.trim().to_owned();
-
-            traits.push("FirstThreadOnMacOs");
         }
+        minecraft_arguments = combined.trim().to_owned();
+
+        traits.push("FirstThreadOnMacOs");
     }
 
     let lwjgl_major = lwjgl_version.chars().next().unwrap();
     let mut json = json!({
-        "assetIndex": vanilla_json["assetIndex"],
+        "assetIndex": vanilla_json.asset_index,
         "compatibleJavaMajors": [8, 17, 21],
         "formatVersion":1,
         "libraries": vanilla_libraries,
-        "mainClass": vanilla_json["mainClass"],
+        "mainClass": vanilla_json.main_class,
         "mainJar": main_jar,
         "minecraftArguments": minecraft_arguments,
         "name":"Minecraft",
-        "releaseTime": vanilla_json["releaseTime"],
+        "releaseTime": vanilla_json.release_time,
         "requires": [{
             "suggests": lwjgl_version,
             "uid": if lwjgl_major == '3' {
@@ -266,7 +311,7 @@ async fn get_mmc_launch_json(
                 "org.lwjgl"
             }
         }],
-        "type":vanilla_json["type"],
+        "type": vanilla_json._type,
         "uid":"net.minecraft",
         "version": &version.id
     });
@@ -1,3 +1,7 @@
 pub mod client;
+pub mod installed;
+pub mod launcher_profiles;
 pub mod mmc_pack;
 pub mod server;
+#[cfg(test)]
+mod test_fixtures;
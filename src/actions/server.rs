@@ -1,29 +1,35 @@
 use std::{
+    collections::HashMap,
     ffi::OsStr,
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
-use log::info;
-use serde_json::Value;
+use log::{info, warn};
 use tokio::task::JoinSet;
 use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
 
 use crate::{
-    errors::InstallerError,
+    errors::{InstallerError, PathContext},
     net::{
+        library::MavenCoordinate,
         manifest::MinecraftVersion,
-        meta::{LoaderType, LoaderVersion},
+        meta::{LoaderType, LoaderVersion, ServerLauncher},
     },
+    progress::{Progress, ProgressEvent},
+    security::safe_join,
 };
 
+use super::installed::{InstallKind, Installation};
+
 pub async fn install(
     version: MinecraftVersion,
     loader_type: LoaderType,
     loader_version: LoaderVersion,
     location: PathBuf,
     install_server: bool,
+    progress: Progress,
 ) -> Result<(), InstallerError> {
     install_path(
         &version,
@@ -31,6 +37,7 @@ pub async fn install(
         &loader_version,
         &location,
         install_server,
+        &progress,
     )
     .await?;
 
@@ -51,11 +58,12 @@ async fn install_path(
     loader_version: &LoaderVersion,
     location: &PathBuf,
     install_server: bool,
+    progress: &Progress,
 ) -> Result<(), InstallerError> {
     if !location.exists() {
-        std::fs::create_dir_all(&location)?;
+        std::fs::create_dir_all(location).with_path(location)?;
     }
-    let location = location.canonicalize()?;
+    let location = location.canonicalize().with_path(location)?;
 
     info!(
         "Installing server for {} using {} Loader {} to {}",
@@ -65,14 +73,16 @@ async fn install_path(
         location.to_str().unwrap_or("<not representable>")
     );
 
-    let clear_paths = [location.join(".fabric"), location.join(".quilt")];
+    let clear_paths = LoaderType::all()
+        .iter()
+        .map(|loader| location.join(".".to_owned() + loader.get_name()));
     for path in clear_paths {
         if path.exists() {
             std::fs::remove_dir_all(&path)?;
         }
     }
 
-    let launch_json_str = crate::net::meta::fetch_launch_json(
+    let launch_json = crate::net::meta::fetch_launch_json(
         crate::net::GameSide::Server,
         version,
         loader_type,
@@ -80,84 +90,68 @@ async fn install_path(
     )
     .await?;
 
-    info!("Installing libraries");
-
-    let launch_json = serde_json::from_str::<Value>(&launch_json_str)?;
-
-    if !launch_json.is_object() {
-        return Err(InstallerError(
-            "Cannot create server installation due to server endpoint returning wrong type."
-                .to_owned(),
-        ));
-    }
-
-    let mut main_class = "";
-    let mut launch_main_class: String;
-
-    match loader_type {
-        LoaderType::Fabric => {
-            main_class = &launch_json["mainClass"]
-                .as_str()
-                .ok_or(InstallerError("Could not find main class entry".to_owned()))?;
-            launch_main_class = "net.fabricmc.loader.launch.server.FabricServerLauncher".to_owned();
-        }
-        LoaderType::Quilt => {
-            launch_main_class = launch_json["launcherMainClass"]
-                .as_str()
-                .ok_or(InstallerError("Could not find main class entry".to_owned()))?
-                .to_owned();
-        }
-    }
+    progress.phase("Installing libraries");
 
-    let libraries = launch_json["libraries"]
-        .as_array()
-        .ok_or(InstallerError("No libraries were specified".to_owned()))?;
+    let main_class = launch_json.main_class.as_str();
 
     let mut library_files = JoinSet::new();
 
-    let mut fabric_loader_artifact = None;
-    for library in libraries {
-        let name = library["name"]
-            .as_str()
-            .ok_or(InstallerError("Library had no name!".to_owned()))?
-            .to_owned();
-        let url = library["url"]
-            .as_str()
-            .ok_or(InstallerError("Library had no url!".to_owned()))?
-            .to_owned();
-
-        if name.matches("net\\.fabricmc:fabric-loader:.*").count() > 0 {
-            fabric_loader_artifact = Some(name.clone());
+    let mut loader_artifact = None;
+    for library in &launch_json.libraries {
+        let coordinate = library.coordinate()?;
+        let url = library.url.clone().ok_or_else(|| {
+            InstallerError::metadata(format!("Library {} has no url", library.name))
+        })?;
+
+        if coordinate.module() == loader_type.get_maven_module() {
+            loader_artifact = Some(coordinate.clone());
         }
         let dir = location.join("libraries");
-        library_files.spawn(async move { download_library(&dir, name, url).await });
+        let progress = progress.clone();
+        library_files
+            .spawn(async move { download_library(&dir, &coordinate, &url, &progress).await });
     }
+    progress.send(ProgressEvent::LibrariesQueued(library_files.len()));
 
     let mut downloaded_library_files = Vec::new();
+    let mut failures = Vec::new();
     while let Some(done) = library_files.join_next().await {
         match done {
             Ok(res) => match res {
-                Ok(file) => downloaded_library_files.push(file),
-                Err(e) => {
-                    return Err(InstallerError(
-                        "Failed to download libraries: ".to_owned() + &e.0,
-                    ));
+                Ok(file) => {
+                    progress.send(ProgressEvent::LibraryCompleted);
+                    downloaded_library_files.push(file);
                 }
+                Err(e) => failures.push(e),
             },
-            Err(e) => {
-                return Err(InstallerError(
-                    "Failed to download libraries: ".to_owned() + &e.to_string(),
-                ));
-            }
+            Err(e) => failures.push(e.into()),
         }
     }
 
+    InstallerError::combine("Failed to download libraries", failures)?;
+
     info!("Downloaded {} libraries!", downloaded_library_files.len());
 
-    if let Some(loader) = fabric_loader_artifact {
-        let lib = location.join("libraries").join(split_artifact(&loader));
-        launch_main_class = read_jar_manifest_attribute(&lib, "Main-Class")?;
-    }
+    let launch_main_class = match &loader_type.server_launcher {
+        ServerLauncher::Properties { .. } => {
+            let loader = loader_artifact.ok_or_else(|| {
+                InstallerError::metadata(format!(
+                    "Server launch json does not contain {}",
+                    loader_type.get_maven_module()
+                ))
+            })?;
+            let lib = safe_join(&location.join("libraries"), &loader.path())?;
+            read_jar_manifest_attribute(&lib, "Main-Class")?
+        }
+        ServerLauncher::LaunchJson => {
+            launch_json
+                .launcher_main_class
+                .clone()
+                .ok_or(InstallerError::metadata(
+                    "Server launch json has no launcherMainClass entry",
+                ))?
+        }
+    };
 
     if !location.exists() {
         std::fs::create_dir_all(&location)?;
@@ -174,11 +168,17 @@ async fn install_path(
     .await?;
 
     if install_server {
-        info!("Downloading server jar");
+        progress.phase("Downloading server jar");
         let url = version
             .get_jar_download_url(&crate::net::GameSide::Server)
             .await?;
-        crate::net::download_file(&url.url, &location.join("server.jar")).await?;
+        crate::net::download_file(
+            &url.url,
+            &location.join("server.jar"),
+            Some(&url.checksum()),
+            progress,
+        )
+        .await?;
     }
 
     Ok(())
@@ -202,13 +202,6 @@ async fn create_launch_jar(
 
     zip.start_file("META-INF/MANIFEST.MF", SimpleFileOptions::default())?;
 
-    let mut manifest = Vec::new();
-    writeln!(manifest, "Manifest-Version: 1.0\r")?;
-    writeln!(
-        manifest,
-        "{}\r",
-        wrap_manifest_line(&format!("Main-Class: {}", launch_main_class))
-    )?;
     let mut class_path = String::from("Class-Path: ");
     for library in library_files {
         let relative = library.strip_prefix(install_location)?.to_str();
@@ -217,20 +210,13 @@ async fn create_launch_jar(
         }
     }
 
-    writeln!(manifest, "{}\r", wrap_manifest_line(class_path.trim_end()))?;
-    writeln!(
-        manifest,
-        "{}\r",
-        wrap_manifest_line(&format!("Minecraft-Version: {}\r", version.id))
+    zip.write_all(
+        launch_manifest(launch_main_class, class_path.trim_end(), &version.id).as_bytes(),
     )?;
-    zip.write_all(&manifest)?;
     zip.add_directory("META-INF", SimpleFileOptions::default())?;
 
-    if loader_type == &LoaderType::Fabric {
-        zip.start_file(
-            "fabric-server-launch.properties",
-            SimpleFileOptions::default(),
-        )?;
+    if let ServerLauncher::Properties { file } = &loader_type.server_launcher {
+        zip.start_file(file, SimpleFileOptions::default())?;
         zip.write_all(("launch.mainClass=".to_owned() + main_class + "\n").as_bytes())?;
     }
 
@@ -239,6 +225,19 @@ async fn create_launch_jar(
     Ok(())
 }
 
+/// The manifest of the server launch jar, with every line ending in `\r\n`
+fn launch_manifest(main_class: &str, class_path: &str, minecraft_version: &str) -> String {
+    [
+        "Manifest-Version: 1.0".to_owned(),
+        format!("Main-Class: {}", main_class),
+        class_path.to_owned(),
+        format!("Minecraft-Version: {}", minecraft_version),
+    ]
+    .iter()
+    .map(|line| wrap_manifest_line(line) + "\r\n")
+    .collect()
+}
+
 fn wrap_manifest_line(line: &str) -> String {
     let mut res = String::new();
     let mut count = 0;
@@ -254,51 +253,89 @@ fn wrap_manifest_line(line: &str) -> String {
     res
 }
 
-fn read_jar_manifest_attribute(
-    jar_file: &PathBuf,
-    attribute: &str,
-) -> Result<String, InstallerError> {
-    let attribute = &(attribute.to_owned() + ": ");
-    let file = std::fs::File::open(jar_file)?;
+fn read_jar_manifest_attribute(jar_file: &Path, attribute: &str) -> Result<String, InstallerError> {
+    read_jar_manifest(jar_file)?
+        .remove(attribute)
+        .ok_or_else(|| {
+            InstallerError::metadata(
+                "Couldn't find '".to_owned() + attribute + "' attribute in jar manifest!",
+            )
+        })
+}
+
+/// Reads the main attributes of a jar manifest, joining wrapped lines
+fn read_jar_manifest(jar_file: &Path) -> Result<HashMap<String, String>, InstallerError> {
+    let file = std::fs::File::open(jar_file).with_path(jar_file)?;
     let mut zip = ZipArchive::new(file)?;
 
     let mut manifest = zip.by_name("META-INF/MANIFEST.MF")?;
     let mf_str = std::io::read_to_string(&mut manifest)?;
-    let main_class_line = mf_str.split("\n").find(|line| line.starts_with(attribute));
-    if let Some(line) = main_class_line {
-        let class = line.strip_prefix(attribute);
-        if let Some(name) = class {
-            return Ok(name.to_owned());
+    let mut attributes = HashMap::new();
+    let mut last = None;
+    for line in mf_str.split('\n').map(|line| line.trim_end_matches('\r')) {
+        if let Some(continued) = line.strip_prefix(' ') {
+            if let Some(name) = &last
+                && let Some(value) = attributes.get_mut(name)
+            {
+                *value += continued;
+            }
+        } else if let Some((name, value)) = line.split_once(": ") {
+            attributes.insert(name.to_owned(), value.to_owned());
+            last = Some(name.to_owned());
+        } else if line.is_empty() {
+            // Per-entry sections follow the main attributes
+            break;
         }
     }
+    Ok(attributes)
+}
 
-    Err(InstallerError(
-        "Couldn't find '".to_owned() + attribute + "' attribute in jar manifest!",
-    ))
+/// Finds the Ornithe servers installed in a directory by reading their launch jars
+pub fn find_installations(dir: &Path) -> Result<Vec<Installation>, InstallerError> {
+    let mut out = Vec::new();
+    for loader_type in LoaderType::all() {
+        let launch_jar = dir.join(loader_type.get_name().to_owned() + "-server-launch.jar");
+        if !launch_jar.is_file() {
+            continue;
+        }
+        let manifest = read_jar_manifest(&launch_jar)?;
+        let Some(minecraft_version) = manifest.get("Minecraft-Version") else {
+            warn!("{} has no Minecraft version", launch_jar.display());
+            continue;
+        };
+        let loader_version = manifest.get("Class-Path").and_then(|class_path| {
+            class_path.split_whitespace().find_map(|library| {
+                let path = library.strip_prefix("libraries/")?;
+                let coordinate = MavenCoordinate::from_path(path).ok()?;
+                (coordinate.module() == loader_type.get_maven_module())
+                    .then_some(coordinate.version)
+            })
+        });
+        out.push(Installation {
+            kind: InstallKind::Server,
+            path: dir.to_path_buf(),
+            minecraft_version: minecraft_version.trim().to_owned(),
+            loader_name: loader_type.get_name().to_owned(),
+            loader_version: loader_version.unwrap_or_default(),
+            profiles: Vec::new(),
+        });
+    }
+    Ok(out)
 }
 
 async fn download_library(
     libraries_dir: &PathBuf,
-    name: String,
-    url: String,
+    coordinate: &MavenCoordinate,
+    repository: &str,
+    progress: &Progress,
 ) -> Result<PathBuf, InstallerError> {
-    let split_artifact = split_artifact(&name);
-    let file = libraries_dir.join(&split_artifact);
-    let raw_url = url.to_owned() + &split_artifact;
-    crate::net::download_file(&raw_url, &file).await?;
+    let file = safe_join(libraries_dir, &coordinate.path())?;
+    let raw_url = coordinate.url(repository);
+    crate::net::download_maven_artifact(&raw_url, &file, progress).await?;
 
     Ok(file)
 }
 
-fn split_artifact(artifact: &str) -> String {
-    let parts = artifact.splitn(3, ":").collect::<Vec<&str>>();
-    let group = parts.get(0).unwrap().replace(".", "/");
-    let name = parts.get(1).unwrap();
-    let version = parts.get(2).unwrap();
-
-    group + "/" + name + "/" + version + "/" + name + "-" + version + ".jar"
-}
-
 pub async fn install_and_run<I, S>(
     version: MinecraftVersion,
     loader_type: LoaderType,
@@ -306,6 +343,7 @@ pub async fn install_and_run<I, S>(
     location: PathBuf,
     java: Option<&PathBuf>,
     args: Option<I>,
+    progress: Progress,
 ) -> Result<(), InstallerError>
 where
     I: IntoIterator<Item = S>,
@@ -324,8 +362,17 @@ where
     }
 
     if needs_install {
-        install_path(&version, &loader_type, &loader_version, &location, true).await?;
+        install_path(
+            &version,
+            &loader_type,
+            &loader_version,
+            &location,
+            true,
+            &progress,
+        )
+        .await?;
     }
+    drop(progress);
 
     let mut java_binary = "java".to_owned();
     if let Some(arg) = java {
@@ -348,3 +395,42 @@ where
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    use super::{find_installations, launch_manifest};
+
+    #[test]
+    fn finds_installations_from_launch_jars() {
+        let dir = tempfile::tempdir().unwrap();
+        let jar = std::fs::File::create(dir.path().join("fabric-server-launch.jar")).unwrap();
+        let mut zip = ZipWriter::new(jar);
+        zip.start_file("META-INF/MANIFEST.MF", SimpleFileOptions::default())
+            .unwrap();
+        let class_path = "Class-Path: libraries/net/ornithemc/calamus-intermediary/1.8.9/calamus-intermediary-1.8.9.jar \
+            libraries/net/fabricmc/fabric-loader/0.16.10/fabric-loader-0.16.10.jar";
+        let manifest = launch_manifest(
+            "net.fabricmc.loader.launch.server.FabricServerLauncher",
+            class_path,
+            "1.8.9",
+        );
+        assert!(!manifest.contains("\r\r"));
+        assert_eq!(
+            manifest.matches('\n').count(),
+            manifest.matches("\r\n").count()
+        );
+        assert!(manifest.ends_with("Minecraft-Version: 1.8.9\r\n"));
+        zip.write_all(manifest.as_bytes()).unwrap();
+        zip.finish().unwrap();
+
+        let installations = find_installations(dir.path()).unwrap();
+        assert_eq!(installations.len(), 1);
+        assert_eq!(installations[0].loader_name, "fabric");
+        assert_eq!(installations[0].loader_version, "0.16.10");
+        assert_eq!(installations[0].minecraft_version, "1.8.9");
+    }
+}
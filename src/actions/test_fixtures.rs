@@ -0,0 +1,34 @@
+//! Helpers shared by the tests of the actions
+
+use std::path::Path;
+
+use serde_json::Value;
+use tempfile::TempDir;
+
+/// Copies a directory of `tests/fixtures/launcher` to a temporary directory
+pub fn fixture(name: &str) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let source = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/launcher")
+        .join(name);
+    for entry in std::fs::read_dir(source).unwrap() {
+        let entry = entry.unwrap();
+        std::fs::copy(entry.path(), dir.path().join(entry.file_name())).unwrap();
+    }
+    dir
+}
+
+/// Parses a json file of the given directory
+pub fn read(dir: &Path, name: &str) -> Value {
+    serde_json::from_str(&std::fs::read_to_string(dir.join(name)).unwrap()).unwrap()
+}
+
+/// The sorted names of the files in a directory
+pub fn files(dir: &Path) -> Vec<String> {
+    let mut files = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
@@ -0,0 +1,349 @@
+use std::{path::PathBuf, sync::OnceLock};
+
+use log::warn;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    errors::{InstallerError, PathContext},
+    net::meta::LoaderType,
+};
+
+pub const DEFAULT_META_URL: &str = "https://meta.ornithemc.net";
+pub const DEFAULT_MANIFEST_URL: &str = "https://skyrising.github.io/mc-versions";
+pub const DEFAULT_MAVEN_URL: &str = "https://maven.ornithemc.net/releases";
+/// Hosts (including their subdomains) that urls from remote metadata may point to
+pub const DEFAULT_ALLOWED_HOSTS: [&str; 6] = [
+    "ornithemc.net",
+    "fabricmc.net",
+    "quiltmc.org",
+    "mojang.com",
+    "minecraft.net",
+    "skyrising.github.io",
+];
+
+const CONFIG_ENV: &str = "ORNITHE_INSTALLER_CONFIG";
+const META_URL_ENV: &str = "ORNITHE_META_URL";
+const MANIFEST_URL_ENV: &str = "ORNITHE_MANIFEST_URL";
+const MAVEN_URL_ENV: &str = "ORNITHE_MAVEN_URL";
+const TIMEOUT_ENV: &str = "ORNITHE_TIMEOUT";
+const CONNECT_TIMEOUT_ENV: &str = "ORNITHE_CONNECT_TIMEOUT";
+const READ_TIMEOUT_ENV: &str = "ORNITHE_READ_TIMEOUT";
+const PROXY_ENV: &str = "ORNITHE_PROXY";
+const NO_PROXY_ENV: &str = "ORNITHE_NO_PROXY";
+const CA_BUNDLE_ENV: &str = "ORNITHE_CA_BUNDLE";
+const NO_CACHE_ENV: &str = "ORNITHE_NO_CACHE";
+const OFFLINE_ENV: &str = "ORNITHE_OFFLINE";
+const RETRIES_ENV: &str = "ORNITHE_RETRIES";
+const RETRY_DELAY_ENV: &str = "ORNITHE_RETRY_DELAY";
+const MAX_DOWNLOADS_ENV: &str = "ORNITHE_MAX_DOWNLOADS";
+const MAX_BANDWIDTH_ENV: &str = "ORNITHE_MAX_BANDWIDTH";
+const ALLOWED_HOSTS_ENV: &str = "ORNITHE_ALLOWED_HOSTS";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Deserialize, Clone)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    /// Ornithe meta endpoints, tried in order until one responds
+    pub meta_urls: Vec<String>,
+    /// Version manifest endpoints, tried in order until one responds
+    pub manifest_urls: Vec<String>,
+    /// Maven repository the intermediary libraries are resolved from
+    pub maven_url: String,
+    /// Timeout in seconds for a single metadata request
+    pub timeout: u64,
+    /// Timeout in seconds for establishing a connection
+    pub connect_timeout: u64,
+    /// Timeout in seconds between two reads of a response
+    pub read_timeout: u64,
+    /// HTTP(S) or SOCKS5 proxy to use, credentials may be given in the url
+    pub proxy: Option<String>,
+    /// Comma-separated list of hosts that are reached without the proxy
+    pub no_proxy: Option<String>,
+    /// PEM file with additional root certificates to trust
+    pub ca_bundle: Option<PathBuf>,
+    /// Whether to bypass the on-disk metadata cache
+    pub no_cache: bool,
+    /// Whether to resolve everything from the cache without network access
+    pub offline: bool,
+    /// How often a failed request is attempted before giving up
+    pub retries: u32,
+    /// Initial delay in milliseconds before retrying a failed request
+    pub retry_delay: u64,
+    /// How many files may be downloaded at the same time
+    pub max_downloads: usize,
+    /// Combined download rate limit in bytes per second, 0 for no limit
+    #[serde(deserialize_with = "deserialize_size")]
+    pub max_bandwidth: u64,
+    /// Hosts that libraries and downloads listed in remote metadata may be fetched from.
+    /// The configured endpoints are always allowed.
+    pub allowed_hosts: Vec<String>,
+    /// Additional loaders, replacing built-in loaders of the same name
+    pub loaders: Vec<LoaderType>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            meta_urls: vec![DEFAULT_META_URL.to_owned()],
+            manifest_urls: vec![DEFAULT_MANIFEST_URL.to_owned()],
+            maven_url: DEFAULT_MAVEN_URL.to_owned(),
+            timeout: 30,
+            connect_timeout: 10,
+            read_timeout: 30,
+            proxy: None,
+            no_proxy: None,
+            ca_bundle: None,
+            no_cache: false,
+            offline: false,
+            retries: 3,
+            retry_delay: 500,
+            max_downloads: 8,
+            max_bandwidth: 0,
+            allowed_hosts: DEFAULT_ALLOWED_HOSTS.map(str::to_owned).to_vec(),
+            loaders: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration file (if present) and applies
+    /// overrides from the environment on top of it.
+    pub fn load(path: Option<&PathBuf>) -> Result<Config, InstallerError> {
+        let path = path
+            .cloned()
+            .or_else(|| std::env::var_os(CONFIG_ENV).map(PathBuf::from));
+
+        let mut config = match path {
+            Some(path) => Config::read(&path)?,
+            None => match config_dir().map(|dir| dir.join("config.json")) {
+                Some(path) if path.exists() => Config::read(&path)?,
+                _ => Config::default(),
+            },
+        };
+
+        if let Some(urls) = env_list(META_URL_ENV) {
+            config.meta_urls = urls;
+        }
+        if let Some(urls) = env_list(MANIFEST_URL_ENV) {
+            config.manifest_urls = urls;
+        }
+        if let Ok(url) = std::env::var(MAVEN_URL_ENV) {
+            config.maven_url = url;
+        }
+        if let Some(hosts) = env_list(ALLOWED_HOSTS_ENV) {
+            config.allowed_hosts = hosts;
+        }
+        if let Some(timeout) = env_number(TIMEOUT_ENV)? {
+            config.timeout = timeout;
+        }
+        if let Some(timeout) = env_number(CONNECT_TIMEOUT_ENV)? {
+            config.connect_timeout = timeout;
+        }
+        if let Some(timeout) = env_number(READ_TIMEOUT_ENV)? {
+            config.read_timeout = timeout;
+        }
+        if let Ok(proxy) = std::env::var(PROXY_ENV) {
+            config.proxy = Some(proxy);
+        }
+        if let Ok(no_proxy) = std::env::var(NO_PROXY_ENV) {
+            config.no_proxy = Some(no_proxy);
+        }
+        if let Some(ca_bundle) = std::env::var_os(CA_BUNDLE_ENV) {
+            config.ca_bundle = Some(PathBuf::from(ca_bundle));
+        }
+        if let Some(retries) = env_number(RETRIES_ENV)? {
+            config.retries = retries;
+        }
+        if let Some(delay) = env_number(RETRY_DELAY_ENV)? {
+            config.retry_delay = delay;
+        }
+        if let Some(max_downloads) = env_number(MAX_DOWNLOADS_ENV)? {
+            config.max_downloads = max_downloads;
+        }
+        if let Ok(bandwidth) = std::env::var(MAX_BANDWIDTH_ENV) {
+            config.max_bandwidth = parse_size(&bandwidth)
+                .map_err(|e| InstallerError::UserInput(format!("{MAX_BANDWIDTH_ENV}: {e}")))?;
+        }
+        if std::env::var_os(NO_CACHE_ENV).is_some() {
+            config.no_cache = true;
+        }
+        if std::env::var_os(OFFLINE_ENV).is_some() {
+            config.offline = true;
+        }
+        for loader in &config.loaders {
+            loader.validate()?;
+        }
+
+        Ok(config)
+    }
+
+    fn read(path: &PathBuf) -> Result<Config, InstallerError> {
+        let content = std::fs::read_to_string(path).with_path(path)?;
+        serde_json::from_str(&content).map_err(|e| {
+            InstallerError::UserInput(format!(
+                "Failed to parse config file {}: {}",
+                path.display(),
+                e
+            ))
+        })
+    }
+}
+
+/// The config file used when none is given on the command line
+pub fn config_file() -> Option<PathBuf> {
+    std::env::var_os(CONFIG_ENV)
+        .map(PathBuf::from)
+        .or_else(|| config_dir().map(|dir| dir.join("config.json")))
+}
+
+/// Sets the given keys in the config file, keeping all other settings.
+/// Keys with a null value are removed.
+pub fn update_file(values: Map<String, Value>) -> Result<PathBuf, InstallerError> {
+    let path = config_file()
+        .ok_or_else(|| InstallerError::other("Unable to determine config file location"))?;
+    let mut content = match std::fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str::<Map<String, Value>>(&content).map_err(|e| {
+            InstallerError::UserInput(format!(
+                "Failed to parse config file {}: {}",
+                path.display(),
+                e
+            ))
+        })?,
+        Err(_) => Map::new(),
+    };
+    for (key, value) in values {
+        if value.is_null() {
+            content.remove(&key);
+        } else {
+            content.insert(key, value);
+        }
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).with_path(parent)?;
+    }
+    std::fs::write(&path, serde_json::to_string_pretty(&content)?).with_path(&path)?;
+    Ok(path)
+}
+
+fn env_list(name: &str) -> Option<Vec<String>> {
+    std::env::var(name).ok().map(|value| {
+        value
+            .split(',')
+            .map(|s| s.trim().to_owned())
+            .filter(|s| !s.is_empty())
+            .collect()
+    })
+}
+
+fn env_number<T: std::str::FromStr>(name: &str) -> Result<Option<T>, InstallerError> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| InstallerError::UserInput(format!("{name} must be a number"))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Parses a byte count with an optional binary suffix, e.g. `512K` or `2M`.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, factor) = match value.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&value[..i], 1024),
+        Some((i, 'm' | 'M')) => (&value[..i], 1024 * 1024),
+        Some((i, 'g' | 'G')) => (&value[..i], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    let number = number
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| format!("'{value}' is not a valid size, expected e.g. 512K or 2M"))?;
+    number
+        .checked_mul(factor)
+        .ok_or_else(|| format!("'{value}' is too large a size"))
+}
+
+fn deserialize_size<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(bytes) => Ok(bytes),
+        Size::Text(text) => parse_size(&text).map_err(serde::de::Error::custom),
+    }
+}
+
+/// Sets the configuration used for the rest of the run.
+/// Has no effect if the configuration was already accessed.
+pub fn init(config: Config) {
+    if CONFIG.set(config).is_err() {
+        warn!("Configuration was already initialized, ignoring overrides");
+    }
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| {
+        Config::load(None).unwrap_or_else(|e| {
+            warn!("{}, using defaults", e.report());
+            Config::default()
+        })
+    })
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| crate::dirs::home_dir().map(|p| p.join(".config")))
+        .map(|p| p.join("ornithe-installer"))
+}
+
+#[cfg(target_os = "windows")]
+pub fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("APPDATA").map(|p| PathBuf::from(p).join("ornithe-installer"))
+}
+
+#[cfg(target_os = "macos")]
+pub fn config_dir() -> Option<PathBuf> {
+    crate::dirs::home_dir().map(|p| p.join("Library/Application Support/ornithe-installer"))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn cache_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| crate::dirs::home_dir().map(|p| p.join(".cache")))
+        .map(|p| p.join("ornithe-installer"))
+}
+
+#[cfg(target_os = "windows")]
+pub fn cache_dir() -> Option<PathBuf> {
+    std::env::var_os("LOCALAPPDATA").map(|p| PathBuf::from(p).join("ornithe-installer"))
+}
+
+#[cfg(target_os = "macos")]
+pub fn cache_dir() -> Option<PathBuf> {
+    crate::dirs::home_dir().map(|p| p.join("Library/Caches/ornithe-installer"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_size;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size(" 512K "), Ok(512 * 1024));
+        assert_eq!(parse_size("2m"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Ok(1024 * 1024 * 1024));
+        assert!(parse_size("fast").is_err());
+        assert!(parse_size("-1K").is_err());
+        assert!(parse_size("99999999999G").is_err());
+    }
+}
@@ -0,0 +1,7 @@
+use std::path::PathBuf;
+
+/// The home directory of the current user
+pub fn home_dir() -> Option<PathBuf> {
+    #[allow(deprecated)]
+    std::env::home_dir()
+}
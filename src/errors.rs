@@ -1,40 +1,306 @@
-use std::{fmt::Debug, path::StripPrefixError};
+use std::{
+    error::Error,
+    fmt::{Debug, Display},
+    path::{Path, PathBuf, StripPrefixError},
+};
+
+type Source = Box<dyn Error + Send + Sync>;
 
 #[derive(Debug)]
-pub struct InstallerError(pub String);
+pub enum InstallerError {
+    /// A request failed or the server answered with an error
+    Network {
+        url: Option<String>,
+        source: reqwest::Error,
+    },
+    /// A resource is needed that has not been cached for offline use
+    Offline { url: String },
+    /// A downloaded file did not match its expected checksum or size
+    Integrity(VerificationError),
+    /// Reading or writing local files failed
+    Filesystem {
+        path: Option<PathBuf>,
+        source: std::io::Error,
+    },
+    /// A requested version or other piece of metadata does not exist
+    NotFound(String),
+    /// Remote metadata could not be understood
+    Metadata {
+        message: String,
+        source: Option<Source>,
+    },
+    /// Invalid arguments, settings or local files provided by the user
+    UserInput(String),
+    /// Remote metadata refers to an untrusted location
+    Security(String),
+    /// Anything else, e.g. the window failing to open
+    Other {
+        message: String,
+        source: Option<Source>,
+    },
+    /// Several independent operations failed, e.g. parallel downloads
+    Multiple {
+        message: String,
+        errors: Vec<InstallerError>,
+    },
+}
+
+impl InstallerError {
+    pub fn metadata(message: impl Into<String>) -> Self {
+        InstallerError::Metadata {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn other(message: impl Into<String>) -> Self {
+        InstallerError::Other {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// Combines the errors of several independent operations. There is no error
+    /// if the list is empty, and a single error is returned as it is.
+    pub fn combine(
+        message: impl Into<String>,
+        mut errors: Vec<InstallerError>,
+    ) -> Result<(), InstallerError> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(InstallerError::Multiple {
+                message: message.into(),
+                errors,
+            }),
+        }
+    }
+
+    /// Describes the error along with all of its causes
+    pub fn report(&self) -> String {
+        let mut message = self.to_string();
+        let mut source = self.source();
+        while let Some(cause) = source {
+            message += &format!(": {}", cause);
+            source = cause.source();
+        }
+        message
+    }
+
+    /// Exit code used by the CLI for this category of error. Combined
+    /// errors use the code of their category if they all share one.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            InstallerError::Multiple { errors, .. } => {
+                let mut codes = errors.iter().map(InstallerError::exit_code);
+                match codes.next() {
+                    Some(code) if codes.all(|other| other == code) => code,
+                    _ => 1,
+                }
+            }
+            InstallerError::Other { .. } => 1,
+            InstallerError::UserInput(_) => 2,
+            InstallerError::NotFound(_) => 3,
+            InstallerError::Network { .. } => 4,
+            InstallerError::Offline { .. } => 5,
+            InstallerError::Integrity(_) => 6,
+            InstallerError::Filesystem { .. } => 7,
+            InstallerError::Metadata { .. } => 8,
+            InstallerError::Security(_) => 9,
+        }
+    }
+}
+
+impl Display for InstallerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InstallerError::Network { url: Some(url), .. } => {
+                write!(f, "Network error while fetching {}", url)
+            }
+            InstallerError::Network { url: None, .. } => write!(f, "Network error"),
+            InstallerError::Offline { url } => write!(f, "{} is not available offline", url),
+            InstallerError::Integrity(e) => Display::fmt(e, f),
+            InstallerError::Filesystem {
+                path: Some(path), ..
+            } => write!(f, "Failed to access {}", path.display()),
+            InstallerError::Filesystem { path: None, .. } => write!(f, "Filesystem error"),
+            InstallerError::NotFound(message)
+            | InstallerError::UserInput(message)
+            | InstallerError::Security(message)
+            | InstallerError::Metadata { message, .. }
+            | InstallerError::Other { message, .. } => write!(f, "{}", message),
+            InstallerError::Multiple { message, errors } => {
+                let mut reports = errors.iter().map(|e| e.report()).collect::<Vec<_>>();
+                reports.sort();
+                write!(f, "{}:\n - {}", message, reports.join("\n - "))
+            }
+        }
+    }
+}
+
+impl Error for InstallerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InstallerError::Network { source, .. } => Some(source),
+            InstallerError::Filesystem { source, .. } => Some(source),
+            InstallerError::Metadata { source, .. } | InstallerError::Other { source, .. } => {
+                source.as_deref().map(|e| e as &(dyn Error + 'static))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Attaches the file involved to filesystem errors
+pub trait PathContext<T> {
+    fn with_path(self, path: &Path) -> Result<T, InstallerError>;
+}
+
+impl<T> PathContext<T> for Result<T, std::io::Error> {
+    fn with_path(self, path: &Path) -> Result<T, InstallerError> {
+        self.map_err(|source| InstallerError::Filesystem {
+            path: Some(path.to_path_buf()),
+            source,
+        })
+    }
+}
 
 impl From<eframe::Error> for InstallerError {
     fn from(value: eframe::Error) -> Self {
-        InstallerError(format!("{:?}", value))
+        InstallerError::other(format!("Failed to open window: {}", value))
     }
 }
 
 impl From<reqwest::Error> for InstallerError {
     fn from(value: reqwest::Error) -> Self {
-        InstallerError(format!("{:?}", value))
+        // Redirects to untrusted locations are refused by the redirect policy
+        let mut source = value.source();
+        while let Some(cause) = source {
+            if let Some(InstallerError::Security(message)) = cause.downcast_ref() {
+                return InstallerError::Security(message.clone());
+            }
+            source = cause.source();
+        }
+        if value.status() == Some(reqwest::StatusCode::NOT_FOUND) {
+            return InstallerError::NotFound(match value.url() {
+                Some(url) => format!("{} does not exist", url),
+                None => "The requested resource does not exist".to_owned(),
+            });
+        }
+        InstallerError::Network {
+            url: value.url().map(|u| u.to_string()),
+            source: value,
+        }
     }
 }
 
+/// For json served by the metadata endpoints. Local files are
+/// mapped explicitly, naming the file that failed to parse.
 impl From<serde_json::Error> for InstallerError {
     fn from(value: serde_json::Error) -> Self {
-        InstallerError(format!("{:?}", value))
+        InstallerError::Metadata {
+            message: "Failed to parse json".to_owned(),
+            source: Some(Box::new(value)),
+        }
     }
 }
 
 impl From<std::io::Error> for InstallerError {
     fn from(value: std::io::Error) -> Self {
-        InstallerError(format!("{:?}", value))
+        InstallerError::Filesystem {
+            path: None,
+            source: value,
+        }
     }
 }
 
 impl From<zip::result::ZipError> for InstallerError {
     fn from(value: zip::result::ZipError) -> Self {
-        InstallerError(format!("{:?}", value))
+        match value {
+            zip::result::ZipError::Io(e) => e.into(),
+            e => InstallerError::Other {
+                message: "Failed to process jar/zip file".to_owned(),
+                source: Some(Box::new(e)),
+            },
+        }
     }
 }
 
 impl From<StripPrefixError> for InstallerError {
     fn from(value: StripPrefixError) -> Self {
-        InstallerError(format!("{:?}", value))
+        InstallerError::Other {
+            message: "Failed to relativize path".to_owned(),
+            source: Some(Box::new(value)),
+        }
+    }
+}
+
+impl From<tokio::task::JoinError> for InstallerError {
+    fn from(value: tokio::task::JoinError) -> Self {
+        InstallerError::Other {
+            message: "Background task failed".to_owned(),
+            source: Some(Box::new(value)),
+        }
+    }
+}
+
+/// A downloaded file did not match its expected checksum or size
+#[derive(Debug)]
+pub struct VerificationError {
+    pub url: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Display for VerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Verification of {} failed: expected {}, got {}",
+            self.url, self.expected, self.actual
+        )
+    }
+}
+
+impl From<VerificationError> for InstallerError {
+    fn from(value: VerificationError) -> Self {
+        InstallerError::Integrity(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InstallerError, VerificationError};
+
+    fn offline(url: &str) -> InstallerError {
+        InstallerError::Offline {
+            url: url.to_owned(),
+        }
+    }
+
+    #[test]
+    fn combine_keeps_single_errors() {
+        assert!(InstallerError::combine("Failed", Vec::new()).is_ok());
+        let error = InstallerError::combine("Failed", vec![offline("a")]).unwrap_err();
+        assert!(matches!(error, InstallerError::Offline { .. }));
+    }
+
+    #[test]
+    fn combined_errors_keep_their_shared_exit_code() {
+        let error = InstallerError::combine("Failed", vec![offline("a"), offline("b")]);
+        assert_eq!(error.unwrap_err().exit_code(), 5);
+
+        let mismatch = |url: &str| {
+            InstallerError::Integrity(VerificationError {
+                url: url.to_owned(),
+                expected: "sha1 a".to_owned(),
+                actual: "sha1 b".to_owned(),
+            })
+        };
+        let error = InstallerError::combine("Failed", vec![mismatch("a"), mismatch("b")]);
+        assert_eq!(error.unwrap_err().exit_code(), 6);
+
+        let error = InstallerError::combine("Failed", vec![offline("a"), mismatch("b")]);
+        assert_eq!(error.unwrap_err().exit_code(), 1);
     }
 }
@@ -2,8 +2,12 @@ use env_logger::Env;
 use log::info;
 
 mod actions;
+mod config;
+mod dirs;
 mod errors;
 mod net;
+mod progress;
+mod security;
 mod ui;
 
 static VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -24,5 +28,5 @@ async fn main() {
         }
     }
 
-    crate::ui::cli::run().await
+    std::process::exit(crate::ui::cli::run().await);
 }
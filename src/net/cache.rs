@@ -0,0 +1,155 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use log::{debug, warn};
+use reqwest::{
+    StatusCode,
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::errors::InstallerError;
+
+/// Validators stored alongside a cached response body
+#[derive(Serialize, Deserialize, Default)]
+struct CacheEntry {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+fn http_cache_dir() -> Option<PathBuf> {
+    crate::config::cache_dir().map(|dir| dir.join("http"))
+}
+
+fn entry_paths(url: &str) -> Option<(PathBuf, PathBuf)> {
+    let key = format!("{:x}", Sha1::digest(url.as_bytes()));
+    http_cache_dir().map(|dir| (dir.join(key.clone() + ".json"), dir.join(key + ".body")))
+}
+
+fn read_entry(url: &str) -> Option<(CacheEntry, Vec<u8>)> {
+    let (entry_path, body_path) = entry_paths(url)?;
+    let entry = serde_json::from_slice::<CacheEntry>(&std::fs::read(entry_path).ok()?).ok()?;
+    let body = std::fs::read(body_path).ok()?;
+    if entry.url != url {
+        return None;
+    }
+    Some((entry, body))
+}
+
+fn write_entry(entry: &CacheEntry, body: &[u8]) -> Result<(), InstallerError> {
+    if let Some((entry_path, body_path)) = entry_paths(&entry.url) {
+        if let Some(parent) = entry_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(body_path, body)?;
+        std::fs::write(entry_path, serde_json::to_vec(entry)?)?;
+    }
+    Ok(())
+}
+
+pub fn offline_error(url: &str) -> InstallerError {
+    InstallerError::Offline {
+        url: url.to_owned(),
+    }
+}
+
+/// Fetches the given url, answering from the on-disk cache
+/// if the server confirms the cached copy is still current.
+pub async fn get(url: &str, timeout: Duration) -> Result<Vec<u8>, InstallerError> {
+    crate::security::check_url(url)?;
+    let config = crate::config::get();
+    if config.offline {
+        return read_entry(url)
+            .map(|(_, body)| body)
+            .ok_or_else(|| offline_error(url));
+    }
+    if config.no_cache {
+        let res = super::retry::send(super::client()?.get(url).timeout(timeout))
+            .await?
+            .error_for_status()?;
+        let body = res.bytes().await?.to_vec();
+        super::limits::throttle(body.len() as u64).await;
+        return Ok(body);
+    }
+
+    let cached = read_entry(url);
+
+    let mut request = super::client()?.get(url).timeout(timeout);
+    if let Some((entry, _)) = &cached {
+        if let Some(etag) = &entry.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &entry.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let res = super::retry::send(request).await?;
+    if res.status() == StatusCode::NOT_MODIFIED
+        && let Some((_, body)) = cached
+    {
+        debug!("Using cached response for {}", url);
+        return Ok(body);
+    }
+    let res = res.error_for_status()?;
+
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_owned())
+    };
+    let entry = CacheEntry {
+        url: url.to_owned(),
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+    let body = res.bytes().await?.to_vec();
+    super::limits::throttle(body.len() as u64).await;
+
+    if let Err(e) = write_entry(&entry, &body) {
+        warn!("Failed to cache response for {}: {}", url, e.report());
+    }
+
+    Ok(body)
+}
+
+fn artifact_path(url: &str) -> Option<PathBuf> {
+    let key = format!("{:x}", Sha1::digest(url.as_bytes()));
+    crate::config::cache_dir().map(|dir| dir.join("artifacts").join(key))
+}
+
+/// Keeps a copy of a downloaded file for later offline installs.
+pub fn store_artifact(url: &str, file: &Path) -> Result<(), InstallerError> {
+    if let Some(path) = artifact_path(url) {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(file, path)?;
+    }
+    Ok(())
+}
+
+/// Copies a previously downloaded file to `output`.
+pub fn restore_artifact(url: &str, output: &Path) -> Result<(), InstallerError> {
+    match artifact_path(url) {
+        Some(path) if path.exists() => {
+            std::fs::copy(path, output)?;
+            Ok(())
+        }
+        _ => Err(offline_error(url)),
+    }
+}
+
+pub fn clear() -> Result<(), InstallerError> {
+    if let Some(dir) = crate::config::cache_dir()
+        && dir.exists()
+    {
+        std::fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,325 @@
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::errors::InstallerError;
+
+use super::Checksum;
+
+/// Repository of libraries without a url, as assumed by the official launcher
+const MINECRAFT_LIBRARIES_URL: &str = "https://libraries.minecraft.net/";
+
+/// A library entry of a launch json, as used by both
+/// the vanilla version jsons and the Ornithe profiles.
+/// Fields the installer does not use are kept as-is.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Library {
+    pub name: String,
+    /// Maven repository the library is resolved from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downloads: Option<LibraryDownloads>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Library {
+    pub fn coordinate(&self) -> Result<MavenCoordinate, InstallerError> {
+        self.name.parse()
+    }
+
+    /// The files the official launcher needs for this library: its artifact and
+    /// its natives for every platform. Libraries without download entries are
+    /// resolved from their maven repository, with the checksum left unknown.
+    pub fn files(&self) -> Result<Vec<LibraryFile>, InstallerError> {
+        let Some(downloads) = &self.downloads else {
+            let coordinate = self.coordinate()?;
+            let repository = self.url.as_deref().unwrap_or(MINECRAFT_LIBRARIES_URL);
+            return Ok(vec![LibraryFile {
+                path: coordinate.path(),
+                url: coordinate.url(repository),
+                checksum: None,
+            }]);
+        };
+
+        let mut files = Vec::new();
+        if let Some(artifact) = &downloads.artifact {
+            files.push(artifact.file(|| self.coordinate())?);
+        }
+        let natives = self.extra.get("natives").and_then(Value::as_object);
+        for classifier in natives.into_iter().flat_map(|natives| natives.values()) {
+            let Some(classifier) = classifier.as_str() else {
+                continue;
+            };
+            for arch in ["32", "64"] {
+                let classifier = classifier.replace("${arch}", arch);
+                if let Some(artifact) = downloads.classifiers.get(&classifier)
+                    && !files.iter().any(|file| file.url == artifact.url)
+                {
+                    files.push(artifact.file(|| {
+                        let mut coordinate = self.coordinate()?;
+                        coordinate.classifier = Some(classifier.clone());
+                        Ok(coordinate)
+                    })?);
+                }
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// A file of a library, see [Library::files]
+pub struct LibraryFile {
+    /// Path relative to the libraries directory
+    pub path: String,
+    pub url: String,
+    pub checksum: Option<Checksum>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LibraryDownloads {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact: Option<Artifact>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub classifiers: BTreeMap<String, Artifact>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Artifact {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub sha1: String,
+    pub size: u64,
+    pub url: String,
+}
+
+impl Artifact {
+    fn file<F>(&self, coordinate: F) -> Result<LibraryFile, InstallerError>
+    where
+        F: FnOnce() -> Result<MavenCoordinate, InstallerError>,
+    {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => coordinate()?.path(),
+        };
+        Ok(LibraryFile {
+            path,
+            url: self.url.clone(),
+            checksum: Some(Checksum::sha1(&self.sha1, self.size)),
+        })
+    }
+}
+
+/// A maven artifact in the form `group:artifact:version[:classifier][@extension]`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MavenCoordinate {
+    pub group: String,
+    pub artifact: String,
+    pub version: String,
+    pub classifier: Option<String>,
+    pub extension: String,
+}
+
+impl MavenCoordinate {
+    /// The path of this artifact relative to the root of a maven repository
+    pub fn path(&self) -> String {
+        let mut file_name = format!("{}-{}", self.artifact, self.version);
+        if let Some(classifier) = &self.classifier {
+            file_name += &format!("-{}", classifier);
+        }
+        format!(
+            "{}/{}/{}/{}.{}",
+            self.group.replace('.', "/"),
+            self.artifact,
+            self.version,
+            file_name,
+            self.extension
+        )
+    }
+
+    /// Parses a path relative to the root of a maven repository,
+    /// the inverse of [MavenCoordinate::path]
+    pub fn from_path(path: &str) -> Result<MavenCoordinate, InstallerError> {
+        let invalid = || InstallerError::metadata(format!("Invalid maven artifact path {}", path));
+        let parts = path.split('/').collect::<Vec<_>>();
+        let [group @ .., artifact, version, file_name] = &parts[..] else {
+            return Err(invalid());
+        };
+        if group.is_empty() {
+            return Err(invalid());
+        }
+        let rest = file_name
+            .strip_prefix(&format!("{}-{}", artifact, version))
+            .ok_or_else(invalid)?;
+        let (classifier, extension) = match rest.strip_prefix('-') {
+            Some(rest) => {
+                let (classifier, extension) = rest.rsplit_once('.').ok_or_else(invalid)?;
+                (Some(classifier), extension)
+            }
+            None => (None, rest.strip_prefix('.').ok_or_else(invalid)?),
+        };
+        let mut coordinate = format!("{}:{}:{}", group.join("."), artifact, version);
+        if let Some(classifier) = classifier {
+            coordinate += &format!(":{}", classifier);
+        }
+        coordinate += &format!("@{}", extension);
+        coordinate.parse()
+    }
+
+    /// The url of this artifact in the given repository
+    pub fn url(&self, repository: &str) -> String {
+        format!("{}/{}", repository.trim_end_matches('/'), self.path())
+    }
+
+    /// The component uid used by MultiMC and Prism Launcher, e.g. `net.fabricmc.intermediary`
+    pub fn prism_uid(&self) -> String {
+        format!("{}.{}", self.group, self.artifact)
+    }
+
+    /// `group:artifact`, without version, classifier or extension
+    pub fn module(&self) -> String {
+        format!("{}:{}", self.group, self.artifact)
+    }
+}
+
+impl FromStr for MavenCoordinate {
+    type Err = InstallerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InstallerError::metadata(format!("Invalid maven coordinate {}", s));
+        let (coordinate, extension) = match s.split_once('@') {
+            Some((coordinate, extension)) => (coordinate, extension),
+            None => (s, "jar"),
+        };
+        let parts = coordinate.split(':').collect::<Vec<_>>();
+        if parts.iter().any(|part| part.is_empty()) || extension.is_empty() {
+            return Err(invalid());
+        }
+        // Every part ends up in a file path, so only allow
+        // characters that cannot form separators or `..` segments
+        let safe = |part: &str| {
+            part.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '+'))
+                && part.split('.').all(|segment| !segment.is_empty())
+        };
+        if !parts.iter().chain([&extension]).all(|part| safe(part)) {
+            return Err(InstallerError::Security(format!(
+                "Refusing to use unsafe maven coordinate {}",
+                s
+            )));
+        }
+        let (group, artifact, version, classifier) = match parts[..] {
+            [group, artifact, version] => (group, artifact, version, None),
+            [group, artifact, version, classifier] => {
+                (group, artifact, version, Some(classifier.to_owned()))
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(MavenCoordinate {
+            group: group.to_owned(),
+            artifact: artifact.to_owned(),
+            version: version.to_owned(),
+            classifier,
+            extension: extension.to_owned(),
+        })
+    }
+}
+
+impl Display for MavenCoordinate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.group, self.artifact, self.version)?;
+        if let Some(classifier) = &self.classifier {
+            write!(f, ":{}", classifier)?;
+        }
+        if self.extension != "jar" {
+            write!(f, "@{}", self.extension)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MavenCoordinate;
+
+    #[test]
+    fn parses_plain_coordinate() {
+        let coordinate = "net.fabricmc:fabric-loader:0.16.10"
+            .parse::<MavenCoordinate>()
+            .unwrap();
+        assert_eq!(coordinate.group, "net.fabricmc");
+        assert_eq!(coordinate.artifact, "fabric-loader");
+        assert_eq!(coordinate.version, "0.16.10");
+        assert_eq!(coordinate.classifier, None);
+        assert_eq!(coordinate.extension, "jar");
+        assert_eq!(
+            coordinate.path(),
+            "net/fabricmc/fabric-loader/0.16.10/fabric-loader-0.16.10.jar"
+        );
+        assert_eq!(
+            coordinate.url("https://maven.ornithemc.net/releases/"),
+            "https://maven.ornithemc.net/releases/net/fabricmc/fabric-loader/0.16.10/fabric-loader-0.16.10.jar"
+        );
+        assert_eq!(coordinate.to_string(), "net.fabricmc:fabric-loader:0.16.10");
+    }
+
+    #[test]
+    fn parses_classifier_and_extension() {
+        let name = "org.lwjgl.lwjgl:lwjgl-platform:2.9.4:natives-linux@zip";
+        let coordinate = name.parse::<MavenCoordinate>().unwrap();
+        assert_eq!(coordinate.classifier.as_deref(), Some("natives-linux"));
+        assert_eq!(coordinate.extension, "zip");
+        assert_eq!(
+            coordinate.path(),
+            "org/lwjgl/lwjgl/lwjgl-platform/2.9.4/lwjgl-platform-2.9.4-natives-linux.zip"
+        );
+        assert_eq!(coordinate.to_string(), name);
+        assert_eq!(coordinate.module(), "org.lwjgl.lwjgl:lwjgl-platform");
+    }
+
+    #[test]
+    fn parses_paths() {
+        for name in [
+            "net.fabricmc:fabric-loader:0.16.10",
+            "org.lwjgl.lwjgl:lwjgl-platform:2.9.4:natives-linux@zip",
+            "org.example:loader.core:1.0.0-beta.1",
+        ] {
+            let coordinate = name.parse::<MavenCoordinate>().unwrap();
+            assert_eq!(
+                MavenCoordinate::from_path(&coordinate.path()).unwrap(),
+                coordinate
+            );
+        }
+        for path in [
+            "fabric-loader/0.16.10/fabric-loader-0.16.10.jar",
+            "net/fabricmc/fabric-loader/0.16.10/fabric-loader-0.16.9.jar",
+            "net/fabricmc/fabric-loader/0.16.10/fabric-loader-0.16.10",
+            "net/../fabric-loader/0.16.10/fabric-loader-0.16.10.jar",
+        ] {
+            assert!(MavenCoordinate::from_path(path).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_coordinates() {
+        for name in ["net.fabricmc:fabric-loader", "a:b:c:d:e", "a::c", "a:b:c@"] {
+            assert!(name.parse::<MavenCoordinate>().is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_path_traversal() {
+        for name in [
+            "net.fabricmc:..:1.0",
+            "..:fabric-loader:1.0",
+            "net..fabricmc:fabric-loader:1.0",
+            "net.fabricmc:fabric-loader:1.0@../jar",
+            "net.fabricmc:fabric/loader:1.0",
+            "net.fabricmc:fabric-loader:1.0:..",
+        ] {
+            assert!(name.parse::<MavenCoordinate>().is_err(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::{
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+static DOWNLOADS: LazyLock<Semaphore> =
+    LazyLock::new(|| Semaphore::new(crate::config::get().max_downloads.max(1)));
+
+/// Point in time until which the available bandwidth has been handed out
+static BANDWIDTH_RESERVED_UNTIL: LazyLock<Mutex<Instant>> =
+    LazyLock::new(|| Mutex::new(Instant::now()));
+
+/// Waits until another download may be started. The download
+/// slot is released once the returned permit is dropped.
+pub async fn acquire_download() -> SemaphorePermit<'static> {
+    DOWNLOADS
+        .acquire()
+        .await
+        .expect("download semaphore is never closed")
+}
+
+/// Delays the caller so that the combined rate of all
+/// downloads stays below the configured bandwidth limit.
+pub async fn throttle(bytes: u64) {
+    let limit = crate::config::get().max_bandwidth;
+    if limit == 0 {
+        return;
+    }
+    let wait = {
+        let mut reserved_until = BANDWIDTH_RESERVED_UNTIL.lock().unwrap();
+        let now = Instant::now();
+        let start = (*reserved_until).max(now);
+        *reserved_until = start + Duration::from_secs_f64(bytes as f64 / limit as f64);
+        *reserved_until - now
+    };
+    tokio::time::sleep(wait).await;
+}
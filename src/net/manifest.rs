@@ -1,53 +1,118 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    sync::{Arc, LazyLock, Mutex},
+};
+
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use semver::Version;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::{Map, Value};
+use tokio::{sync::OnceCell, task::JoinSet};
 
-use crate::errors::InstallerError;
+use crate::{config::DEFAULT_MANIFEST_URL, errors::InstallerError};
 
-use super::GameSide;
+use super::{
+    Checksum, GameSide,
+    library::{Library, MavenCoordinate},
+};
 
-const LAUNCHER_META_URL: &str = "https://skyrising.github.io/mc-versions/version_manifest.json";
-const VERSION_META_URL: &str = "https://skyrising.github.io/mc-versions/version/manifest/{}.json";
+const LAUNCHER_META_PATH: &str = "/version_manifest.json";
+const VERSION_META_PATH: &str = "/version/manifest/{}.json";
+const RESOURCES_URL: &str = "https://resources.download.minecraft.net";
 
 pub async fn fetch_versions() -> Result<VersionManifest, InstallerError> {
-    super::CLIENT
-        .get(LAUNCHER_META_URL)
-        .send()
-        .await?
-        .json::<VersionManifest>()
+    super::get_mirrored(&crate::config::get().manifest_urls, LAUNCHER_META_PATH).await
+}
+
+type Memoized<T> = Mutex<HashMap<String, Arc<OnceCell<Arc<T>>>>>;
+
+/// Remembers the documents fetched for each Minecraft version during
+/// this run, so that each of them is requested at most once.
+#[derive(Default)]
+pub struct VersionRepository {
+    details: Memoized<VersionDetails>,
+    documents: Memoized<Value>,
+}
+
+static REPOSITORY: LazyLock<VersionRepository> = LazyLock::new(VersionRepository::default);
+
+impl VersionRepository {
+    pub fn get() -> &'static VersionRepository {
+        &REPOSITORY
+    }
+
+    async fn memoize<T, F, Fut>(
+        store: &Memoized<T>,
+        key: &str,
+        fetch: F,
+    ) -> Result<Arc<T>, InstallerError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, InstallerError>>,
+    {
+        let cell = store
+            .lock()
+            .unwrap()
+            .entry(key.to_owned())
+            .or_default()
+            .clone();
+        cell.get_or_try_init(|| async { fetch().await.map(Arc::new) })
+            .await
+            .cloned()
+    }
+
+    pub async fn details(
+        &self,
+        version: &MinecraftVersion,
+    ) -> Result<Arc<VersionDetails>, InstallerError> {
+        Self::memoize(&self.details, &version.id, || {
+            get_manifest_document(&version.details)
+        })
         .await
-        .map_err(|e| e.into())
-}
-
-pub async fn fetch_launch_json(version: &MinecraftVersion) -> Result<String, InstallerError> {
-    let res = super::CLIENT
-        .get(VERSION_META_URL.replace("{}", version.id.as_str()))
-        .send()
-        .await?;
-    if let Some(val) = res.json::<Value>().await?.as_object_mut() {
-        let version_details = fetch_version_details(&version).await?;
-
-        for manifest in version_details.manifests {
-            if let Some(manifest) = super::CLIENT
-                .get(manifest.url)
-                .send()
-                .await?
-                .json::<Value>()
-                .await?
-                .as_object()
-            {
-                build_version_json_from_manifest(val, manifest);
-            }
-        }
+    }
 
-        val.insert(
-            "id".to_string(),
-            Value::String(format!("{}-vanilla", version.id.clone())),
-        );
+    /// The launcher version json for the given version, without its sub-manifests applied
+    pub async fn launch_json(
+        &self,
+        version: &MinecraftVersion,
+    ) -> Result<Arc<Value>, InstallerError> {
+        let path = VERSION_META_PATH.replace("{}", version.id.as_str());
+        Self::memoize(&self.documents, &path, || {
+            super::get_mirrored(&crate::config::get().manifest_urls, &path)
+        })
+        .await
+    }
 
-        return Ok(serde_json::to_string_pretty(val)?);
+    pub async fn manifest(&self, url: &str) -> Result<Arc<Value>, InstallerError> {
+        Self::memoize(&self.documents, url, || get_manifest_document(url)).await
     }
-    Err(InstallerError("Error".to_string()))
+}
+
+pub async fn fetch_launch_json(version: &MinecraftVersion) -> Result<VersionJson, InstallerError> {
+    let repository = VersionRepository::get();
+    let mut res = (*repository.launch_json(version).await?).clone();
+    let Some(val) = res.as_object_mut() else {
+        return Err(InstallerError::metadata(format!(
+            "Launch json for Minecraft {} is not an object",
+            version.id
+        )));
+    };
+    let version_details = repository.details(version).await?;
+
+    for manifest in &version_details.manifests {
+        if let Some(manifest) = repository.manifest(&manifest.url).await?.as_object() {
+            build_version_json_from_manifest(val, manifest);
+        }
+    }
+
+    let mut json =
+        serde_json::from_value::<VersionJson>(res).map_err(|e| InstallerError::Metadata {
+            message: format!("Invalid launch json for Minecraft {}", version.id),
+            source: Some(Box::new(e)),
+        })?;
+    json.id = format!("{}-vanilla", version.id);
+    Ok(json)
 }
 
 fn build_version_json_from_manifest(
@@ -74,15 +139,13 @@ fn build_version_json_from_manifest(
     }
 }
 
-async fn fetch_version_details(
-    version: &MinecraftVersion,
-) -> Result<VersionDetails, reqwest::Error> {
-    super::CLIENT
-        .get(version.details.clone())
-        .send()
-        .await?
-        .json::<VersionDetails>()
-        .await
+async fn get_manifest_document<T: DeserializeOwned>(url: &str) -> Result<T, InstallerError> {
+    super::get_mirrored_url(
+        &crate::config::get().manifest_urls,
+        DEFAULT_MANIFEST_URL,
+        url,
+    )
+    .await
 }
 
 #[allow(dead_code)]
@@ -95,13 +158,26 @@ pub struct VersionManifest {
 #[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct LatestVersions {
-    old_alpha: String,
-    classic_server: String,
-    alpha_server: String,
-    old_beta: String,
-    snapshot: String,
-    release: String,
-    pending: String,
+    old_alpha: Option<String>,
+    classic_server: Option<String>,
+    alpha_server: Option<String>,
+    old_beta: Option<String>,
+    snapshot: Option<String>,
+    release: Option<String>,
+    pending: Option<String>,
+}
+
+impl LatestVersions {
+    /// Resolves aliases like `latest-release` to a version id
+    pub fn resolve(&self, alias: &str) -> Option<&str> {
+        match alias {
+            "latest-release" => self.release.as_deref(),
+            "latest-snapshot" => self.snapshot.as_deref(),
+            "latest-beta" => self.old_beta.as_deref(),
+            "latest-alpha" => self.old_alpha.as_deref(),
+            _ => None,
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -118,26 +194,130 @@ pub struct MinecraftVersion {
 }
 
 impl MinecraftVersion {
-    pub async fn get_id(&self, side: &GameSide) -> Result<String, reqwest::Error> {
-        if fetch_version_details(self).await?.shared_mappings {
+    pub async fn get_id(&self, side: &GameSide) -> Result<String, InstallerError> {
+        if VersionRepository::get()
+            .details(self)
+            .await?
+            .shared_mappings
+        {
             Ok(self.id.clone())
         } else {
             Ok(self.id.clone() + "-" + side.id())
         }
     }
 
+    pub async fn normalized_version(&self) -> Result<Version, InstallerError> {
+        let details = VersionRepository::get().details(self).await?;
+        Version::parse(&details.normalized_version).map_err(|e| InstallerError::Metadata {
+            message: format!(
+                "Invalid normalized version {} for Minecraft {}",
+                details.normalized_version, self.id
+            ),
+            source: Some(Box::new(e)),
+        })
+    }
+
     pub async fn get_jar_download_url(
         &self,
         side: &GameSide,
-    ) -> Result<VersionDownload, reqwest::Error> {
-        let downloads = fetch_version_details(self).await?.downloads;
+    ) -> Result<VersionDownload, InstallerError> {
+        let details = VersionRepository::get().details(self).await?;
         Ok(match side {
-            GameSide::Client => downloads.client,
-            GameSide::Server => downloads.server,
+            GameSide::Client => details.downloads.client.clone(),
+            GameSide::Server => details.downloads.server.clone(),
         })
     }
 }
 
+/// Selects versions from `available`, which may be given as an id, an alias
+/// like `latest-release` or an inclusive range like `1.3..1.7.10` ordered by
+/// normalized version. Either end of a range may be left open. Ranges contain
+/// the version types of their ends, plus snapshots if requested.
+pub async fn select_versions(
+    latest: &LatestVersions,
+    available: &[MinecraftVersion],
+    selector: &str,
+    include_snapshots: bool,
+) -> Result<Vec<MinecraftVersion>, InstallerError> {
+    let Some((from, to)) = selector.split_once("..") else {
+        return Ok(vec![
+            find_version(latest, available, selector.trim())?.clone(),
+        ]);
+    };
+    let bound = |id: &str| {
+        let id = id.trim();
+        (!id.is_empty())
+            .then(|| find_version(latest, available, id))
+            .transpose()
+    };
+    let (from, to) = (bound(from)?, bound(to)?);
+    if from.is_none() && to.is_none() {
+        return Err(InstallerError::UserInput(
+            "A version range needs at least one end".to_owned(),
+        ));
+    }
+    let types = from
+        .iter()
+        .chain(&to)
+        .map(|v| v._type.as_str())
+        .chain(include_snapshots.then_some("snapshot"))
+        .collect::<Vec<_>>();
+
+    // Release dates do not follow the version order, e.g. for backports,
+    // so the normalized version of every candidate has to be looked up
+    let mut candidates = JoinSet::new();
+    for version in available {
+        if types.contains(&version._type.as_str()) {
+            let version = version.clone();
+            candidates.spawn(async move {
+                let normalized = version.normalized_version().await?;
+                Ok::<_, InstallerError>((normalized, version))
+            });
+        }
+    }
+    let from = match from {
+        Some(from) => Some(from.normalized_version().await?),
+        None => None,
+    };
+    let to = match to {
+        Some(to) => Some(to.normalized_version().await?),
+        None => None,
+    };
+
+    let mut selected = Vec::new();
+    while let Some(res) = candidates.join_next().await {
+        let (normalized, version) = res??;
+        if from.as_ref().is_none_or(|from| &normalized >= from)
+            && to.as_ref().is_none_or(|to| &normalized <= to)
+        {
+            selected.push((normalized, version));
+        }
+    }
+    selected.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(selected.into_iter().map(|(_, version)| version).collect())
+}
+
+fn find_version<'a>(
+    latest: &LatestVersions,
+    available: &'a [MinecraftVersion],
+    id: &str,
+) -> Result<&'a MinecraftVersion, InstallerError> {
+    let resolved = latest.resolve(id).unwrap_or(id);
+    available.iter().find(|v| v.id == resolved).ok_or_else(|| {
+        InstallerError::NotFound(if resolved == id {
+            format!(
+                "Could not find Minecraft version {} among supported versions!",
+                id
+            )
+        } else {
+            format!(
+                "{} resolves to Minecraft {}, which is not supported!",
+                id, resolved
+            )
+        })
+    })
+}
+
 #[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct VersionDetails {
@@ -155,14 +335,101 @@ struct VersionDownloads {
     server: VersionDownload,
 }
 
-#[allow(dead_code)]
+/// Launcher version json of a Minecraft version, with its manifests applied
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionJson {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub _type: String,
+    pub main_class: String,
+    pub release_time: String,
+    pub libraries: Vec<Library>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub downloads: BTreeMap<String, VersionDownload>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset_index: Option<AssetIndex>,
+    /// Game arguments of versions before 1.13
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minecraft_arguments: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Arguments>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetIndex {
+    pub id: String,
+    pub sha1: String,
+    pub size: u64,
+    pub url: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl AssetIndex {
+    pub fn checksum(&self) -> Checksum {
+        Checksum::sha1(&self.sha1, self.size)
+    }
+}
+
+/// Contents of the file an [AssetIndex] points to
 #[derive(Deserialize)]
+pub struct AssetObjects {
+    pub objects: BTreeMap<String, AssetObject>,
+}
+
+#[derive(Deserialize)]
+pub struct AssetObject {
+    pub hash: String,
+    pub size: u64,
+}
+
+impl AssetObject {
+    /// Path of the object relative to both the objects directory and the resources url
+    pub fn path(&self) -> Result<String, InstallerError> {
+        if self.hash.len() != 40 || !self.hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(InstallerError::metadata(format!(
+                "Invalid asset hash {}",
+                self.hash
+            )));
+        }
+        Ok(format!("{}/{}", &self.hash[..2], self.hash))
+    }
+
+    pub fn url(&self) -> Result<String, InstallerError> {
+        Ok(format!("{}/{}", RESOURCES_URL, self.path()?))
+    }
+
+    pub fn checksum(&self) -> Checksum {
+        Checksum::sha1(&self.hash, self.size)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Arguments {
+    /// Plain string arguments and objects with rules
+    #[serde(default)]
+    pub game: Vec<Value>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct VersionDownload {
     pub sha1: String,
-    pub size: u32,
+    pub size: u64,
     pub url: String,
 }
 
+impl VersionDownload {
+    pub fn checksum(&self) -> Checksum {
+        Checksum::sha1(&self.sha1, self.size)
+    }
+}
+
 #[derive(Deserialize)]
 pub struct VersionDetailsManifest {
     #[serde(rename = "type")]
@@ -171,30 +438,188 @@ pub struct VersionDetailsManifest {
 }
 
 pub async fn find_lwjgl_version(version: &MinecraftVersion) -> Result<String, InstallerError> {
-    let details = fetch_version_details(&version).await?;
-    for manifest in details.manifests {
-        let manifest = super::CLIENT
-            .get(manifest.url)
-            .send()
-            .await?
-            .json::<Value>()
-            .await?;
+    let repository = VersionRepository::get();
+    let details = repository.details(version).await?;
+    for manifest in &details.manifests {
+        let manifest = repository.manifest(&manifest.url).await?;
 
         if let Some(libs) = manifest["libraries"].as_array() {
             for library in libs {
-                let name = library["name"].clone();
-                if name.is_string() {
-                    let mut name = name.as_str().unwrap().split(":").skip(1);
-                    let artifact = name.next().unwrap();
-                    if artifact == "lwjgl" {
-                        return Ok(name.next().unwrap().to_owned());
-                    }
+                if let Some(coordinate) = library["name"]
+                    .as_str()
+                    .and_then(|name| name.parse::<MavenCoordinate>().ok())
+                    && coordinate.artifact == "lwjgl"
+                {
+                    return Ok(coordinate.version);
                 }
             }
         }
     }
 
-    Err(InstallerError(
+    Err(InstallerError::metadata(
         "Unable to find lwjgl version for Minecraft ".to_owned() + &version.id,
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+    use tokio::sync::OnceCell;
+
+    use crate::errors::InstallerError;
+
+    use super::{LatestVersions, MinecraftVersion, VersionRepository, select_versions};
+
+    /// Versions of the manifest with their normalized versions, oldest first
+    const VERSIONS: [(&str, &str, &str, &str); 7] = [
+        (
+            "b1.7.3",
+            "old_beta",
+            "2011-07-07T22:00:00+00:00",
+            "1.0.0-beta.7.3",
+        ),
+        ("1.2.5", "release", "2012-03-29T22:00:00+00:00", "1.2.5"),
+        (
+            "12w30a",
+            "snapshot",
+            "2012-07-26T22:00:00+00:00",
+            "1.3.0-alpha.12.30.a",
+        ),
+        ("1.3.2", "release", "2012-08-15T22:00:00+00:00", "1.3.2"),
+        ("1.4.7", "release", "2012-12-27T22:00:00+00:00", "1.4.7"),
+        (
+            "13w01a",
+            "snapshot",
+            "2013-01-03T22:00:00+00:00",
+            "1.5.0-alpha.13.1.a",
+        ),
+        // A backport released after newer versions
+        ("1.2.6", "release", "2013-02-01T22:00:00+00:00", "1.2.6"),
+    ];
+
+    fn latest() -> LatestVersions {
+        serde_json::from_value(json!({
+            "old_beta": "b1.7.3",
+            "snapshot": "13w02a",
+            "release": "1.4.7"
+        }))
+        .unwrap()
+    }
+
+    /// The versions of the manifest, with their details already known to the repository
+    fn available() -> Vec<MinecraftVersion> {
+        let mut details = VersionRepository::get().details.lock().unwrap();
+        VERSIONS
+            .iter()
+            .map(|(id, version_type, release_time, normalized)| {
+                let download = json!({ "sha1": "", "size": 0, "url": "" });
+                let version_details = serde_json::from_value(json!({
+                    "manifests": [],
+                    "sharedMappings": false,
+                    "normalizedVersion": normalized,
+                    "downloads": { "client": download, "server": download }
+                }))
+                .unwrap();
+                details.insert(
+                    id.to_string(),
+                    Arc::new(OnceCell::new_with(Some(Arc::new(version_details)))),
+                );
+                serde_json::from_value(json!({
+                    "id": id,
+                    "type": version_type,
+                    "url": "",
+                    "releaseTime": release_time,
+                    "details": ""
+                }))
+                .unwrap()
+            })
+            .collect()
+    }
+
+    async fn select(
+        selector: &str,
+        include_snapshots: bool,
+    ) -> Result<Vec<String>, InstallerError> {
+        let selected =
+            select_versions(&latest(), &available(), selector, include_snapshots).await?;
+        Ok(selected.into_iter().map(|version| version.id).collect())
+    }
+
+    #[test]
+    fn resolves_aliases() {
+        let latest = latest();
+        assert_eq!(latest.resolve("latest-release"), Some("1.4.7"));
+        assert_eq!(latest.resolve("latest-snapshot"), Some("13w02a"));
+        assert_eq!(latest.resolve("latest-beta"), Some("b1.7.3"));
+        assert_eq!(latest.resolve("latest-alpha"), None);
+        assert_eq!(latest.resolve("1.4.7"), None);
+    }
+
+    #[tokio::test]
+    async fn selects_single_versions() {
+        assert_eq!(select("1.3.2", false).await.unwrap(), ["1.3.2"]);
+        assert_eq!(select("latest-release", false).await.unwrap(), ["1.4.7"]);
+        assert_eq!(select("latest-beta", false).await.unwrap(), ["b1.7.3"]);
+        // The latest snapshot is not among the available versions
+        assert!(matches!(
+            select("latest-snapshot", false).await,
+            Err(InstallerError::NotFound(_))
+        ));
+        assert!(matches!(
+            select("1.8.9", false).await,
+            Err(InstallerError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn selects_ranges_by_version_type() {
+        assert_eq!(
+            select("1.2.5..1.4.7", false).await.unwrap(),
+            ["1.2.5", "1.2.6", "1.3.2", "1.4.7"]
+        );
+        assert_eq!(
+            select("1.2.5..1.4.7", true).await.unwrap(),
+            ["1.2.5", "1.2.6", "12w30a", "1.3.2", "1.4.7"]
+        );
+        assert_eq!(
+            select("b1.7.3..1.3.2", false).await.unwrap(),
+            ["b1.7.3", "1.2.5", "1.2.6", "1.3.2"]
+        );
+        assert_eq!(
+            select("12w30a..1.3.2", false).await.unwrap(),
+            ["12w30a", "1.3.2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn selects_out_of_order_releases_by_version() {
+        // 1.2.6 was released after 1.3.2, but belongs between the ends by version
+        assert_eq!(
+            select("1.2.5..1.3.2", false).await.unwrap(),
+            ["1.2.5", "1.2.6", "1.3.2"]
+        );
+        assert_eq!(
+            select("1.3.2..1.4.7", false).await.unwrap(),
+            ["1.3.2", "1.4.7"]
+        );
+    }
+
+    #[tokio::test]
+    async fn selects_open_ranges() {
+        assert_eq!(
+            select("..1.3.2", false).await.unwrap(),
+            ["1.2.5", "1.2.6", "1.3.2"]
+        );
+        assert_eq!(select("1.3.2..", false).await.unwrap(), ["1.3.2", "1.4.7"]);
+        assert_eq!(
+            select("latest-release..", true).await.unwrap(),
+            ["1.4.7", "13w01a"]
+        );
+        assert!(matches!(
+            select("..", false).await,
+            Err(InstallerError::UserInput(_))
+        ));
+    }
+}
@@ -1,67 +1,236 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::LazyLock};
 
-use serde::Deserialize;
-use serde_json::Value;
+use log::warn;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
 
 use crate::errors::InstallerError;
 
-use super::{GameSide, manifest::MinecraftVersion};
-
-const META_URL: &str = "https://meta.ornithemc.net";
+use super::{
+    GameSide,
+    library::{Library, MavenCoordinate},
+    manifest::MinecraftVersion,
+};
 
 #[allow(dead_code)]
 #[derive(Deserialize, Clone)]
 pub struct LoaderVersion {
     pub version: String,
-    stable: bool,
+    pub stable: bool,
     maven: String,
     separator: String,
     build: i32,
-    #[serde(rename(deserialize = "versionNoSide"))]
+    #[serde(default, rename(deserialize = "versionNoSide"))]
     version_no_side: String,
 }
 
-#[derive(PartialEq, Eq, Hash, Clone)]
-pub enum LoaderType {
-    Fabric,
-    Quilt,
+/// An entry of the loader versions listed for a single game version
+#[derive(Deserialize)]
+struct GameLoaderVersion {
+    loader: LoaderVersion,
+}
+
+impl LoaderVersion {
+    pub fn semver(&self) -> Option<Version> {
+        Version::parse(&self.version).ok()
+    }
+}
+
+/// Picks a loader version from a list sorted newest first. The selector may be
+/// `latest`, `latest-stable`, an exact version or a semver range like `>=0.16, <0.17`.
+/// Ranges resolve to the newest matching stable version if there is one.
+pub fn select_loader_version<'a>(
+    versions: &'a [LoaderVersion],
+    selector: &str,
+) -> Result<&'a LoaderVersion, InstallerError> {
+    let selector = selector.trim();
+    let found = match selector {
+        "latest" => versions.first(),
+        "latest-stable" => versions.iter().find(|v| v.stable),
+        _ if Version::parse(selector).is_ok() || versions.iter().any(|v| v.version == selector) => {
+            versions.iter().find(|v| v.version == selector)
+        }
+        _ => {
+            let range = VersionReq::parse(selector).map_err(|e| {
+                InstallerError::UserInput(format!("Invalid loader version {}: {}", selector, e))
+            })?;
+            let matching = versions
+                .iter()
+                .filter(|v| v.semver().is_some_and(|version| range.matches(&version)));
+            matching
+                .clone()
+                .find(|v| v.stable)
+                .or_else(|| matching.clone().next())
+        }
+    };
+    found.ok_or_else(|| {
+        InstallerError::NotFound(format!("Could not find loader version {}", selector))
+    })
+}
+
+const BUILTIN_LOADERS: &str = include_str!("../../res/loaders.json");
+
+static LOADERS: LazyLock<Vec<LoaderType>> = LazyLock::new(|| {
+    let mut loaders = serde_json::from_str::<Vec<LoaderType>>(BUILTIN_LOADERS)
+        .expect("built-in loader table is valid");
+    for loader in &crate::config::get().loaders {
+        match loaders.iter_mut().find(|l| l.name == loader.name) {
+            Some(existing) => *existing = loader.clone(),
+            None => loaders.push(loader.clone()),
+        }
+    }
+    loaders
+});
+
+/// A mod loader that can be installed. The built-in loaders
+/// can be extended or replaced through the `loaders` config key.
+#[derive(Deserialize, PartialEq, Eq, Hash, Clone, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct LoaderType {
+    /// Identifier used on the command line and in file names, e.g. `fabric`
+    pub name: String,
+    /// Name shown to users, e.g. `Fabric`
+    pub display_name: String,
+    /// Meta endpoint listing the loader versions, either relative
+    /// to the configured meta urls or an absolute url
+    pub meta_endpoint: String,
+    /// Maven module of the loader itself, e.g. `net.fabricmc:fabric-loader`
+    pub maven_module: String,
+    pub server_launcher: ServerLauncher,
+    /// Libraries to replace in the launch jsons served by the meta
+    #[serde(default)]
+    pub library_rewrites: Vec<LibraryRewrite>,
+}
+
+/// How the server launch jar starts the loader
+#[derive(Deserialize, PartialEq, Eq, Hash, Clone, Debug)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ServerLauncher {
+    /// Starts the `Main-Class` of the loader jar, which reads
+    /// the game's main class from the given properties file
+    Properties { file: String },
+    /// Starts the `launcherMainClass` given by the server launch json
+    LaunchJson,
+}
+
+#[derive(Deserialize, PartialEq, Eq, Hash, Clone, Debug)]
+pub struct LibraryRewrite {
+    /// Maven module to replace, e.g. `net.fabricmc:intermediary`
+    pub from: String,
+    /// Maven module to use instead
+    pub to: String,
+    /// Repository the replacement is resolved from, the configured maven url by default
+    #[serde(default)]
+    pub repository: Option<String>,
 }
 
 impl LoaderType {
-    pub fn get_name(&self) -> &str {
-        match self {
-            LoaderType::Fabric => "fabric",
-            LoaderType::Quilt => "quilt",
+    /// The built-in loaders followed by those from the config,
+    /// where configured loaders replace built-in ones of the same name.
+    pub fn all() -> &'static [LoaderType] {
+        &LOADERS
+    }
+
+    pub fn find(name: &str) -> Option<LoaderType> {
+        LoaderType::all()
+            .iter()
+            .find(|l| l.name.eq_ignore_ascii_case(name))
+            .cloned()
+    }
+
+    /// Checks the parts of a loader definition that end up in file names
+    /// and library coordinates, done when the configuration is loaded.
+    pub fn validate(&self) -> Result<(), InstallerError> {
+        let invalid = |what: &str, value: &str| {
+            InstallerError::UserInput(format!(
+                "Invalid {} {} for loader {}",
+                what, value, self.name
+            ))
+        };
+        if self.name.is_empty()
+            || !self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        {
+            return Err(invalid("name", &self.name));
+        }
+        let module = |value: &str| {
+            value.split(':').count() == 2
+                && format!("{}:0", value).parse::<MavenCoordinate>().is_ok()
+        };
+        if !module(&self.maven_module) {
+            return Err(invalid("maven module", &self.maven_module));
+        }
+        for rewrite in &self.library_rewrites {
+            if !module(&rewrite.from) {
+                return Err(invalid("library rewrite source", &rewrite.from));
+            }
+            if !module(&rewrite.to) {
+                return Err(invalid("library rewrite target", &rewrite.to));
+            }
         }
+        Ok(())
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
     }
 
     pub fn get_localized_name(&self) -> &str {
-        match self {
-            LoaderType::Fabric => "Fabric",
-            LoaderType::Quilt => "Quilt",
-        }
+        &self.display_name
     }
 
-    pub fn get_maven_uid(&self) -> &str {
-        match self {
-            LoaderType::Fabric => "net.fabricmc.fabric-loader",
-            LoaderType::Quilt => "org.quiltmc.quilt-loader",
-        }
+    pub fn get_maven_uid(&self) -> String {
+        self.maven_module.replace(':', ".")
     }
 
-    pub fn get_maven_name_start(&self) -> &str {
-        match self {
-            LoaderType::Fabric => "net.fabricmc:fabric-loader",
-            LoaderType::Quilt => "org.quiltmc:quilt-loader",
+    pub fn get_maven_module(&self) -> &str {
+        &self.maven_module
+    }
+
+    /// Fetches a document below this loader's meta endpoint
+    async fn fetch<T: DeserializeOwned>(&self, path: &str) -> Result<T, InstallerError> {
+        if self.meta_endpoint.contains("://") {
+            super::get_mirrored(std::slice::from_ref(&self.meta_endpoint), path).await
+        } else {
+            super::get_mirrored(
+                &crate::config::get().meta_urls,
+                &(self.meta_endpoint.clone() + path),
+            )
+            .await
         }
     }
+
+    fn rewrite_library(&self, library: &mut Library) -> Result<(), InstallerError> {
+        let mut coordinate = library.coordinate()?;
+        let module = coordinate.module();
+        let Some(rewrite) = self.library_rewrites.iter().find(|r| r.from == module) else {
+            return Ok(());
+        };
+        let (group, artifact) = rewrite
+            .to
+            .split_once(':')
+            .expect("library rewrites are validated when loading");
+        coordinate.group = group.to_owned();
+        coordinate.artifact = artifact.to_owned();
+        library.name = coordinate.to_string();
+        library.url = Some(
+            rewrite
+                .repository
+                .clone()
+                .unwrap_or_else(|| crate::config::get().maven_url.clone()),
+        );
+        Ok(())
+    }
 }
 
 impl GameSide {
     fn launch_json_endpoint(&self) -> &str {
         match self {
-            GameSide::Client => "/v3/versions/{}-loader/{}/{}/profile/json",
-            GameSide::Server => "/v3/versions/{}-loader/{}/{}/server/json",
+            GameSide::Client => "/{}/{}/profile/json",
+            GameSide::Server => "/{}/{}/server/json",
         }
     }
 }
@@ -71,84 +240,99 @@ pub async fn fetch_launch_json(
     version: &MinecraftVersion,
     loader_type: &LoaderType,
     loader_version: &LoaderVersion,
-) -> Result<String, InstallerError> {
-    let mut text = super::CLIENT
-        .get(
-            META_URL.to_owned()
-                + &side
-                    .launch_json_endpoint()
-                    .replacen("{}", loader_type.get_name(), 1)
-                    .replacen("{}", version.get_id(&side).await?.as_str(), 1)
-                    .replacen("{}", &loader_version.version, 1),
+) -> Result<LaunchProfile, InstallerError> {
+    let mut profile = loader_type
+        .fetch::<LaunchProfile>(
+            &side
+                .launch_json_endpoint()
+                .replacen("{}", version.get_id(&side).await?.as_str(), 1)
+                .replacen("{}", &loader_version.version, 1),
         )
-        .send()
-        .await?
-        .json::<Value>()
         .await?;
-    if let Some(libraries) = text["libraries"].as_object_mut() {
-        for lib in libraries {
-            let lib_mut = lib.1.as_object_mut().unwrap();
-            if let Some(name) = lib_mut.clone()["name"].as_str() {
-                if name.starts_with("net.fabricmc:intermediary") {
-                    lib_mut.insert(
-                        "name".to_string(),
-                        Value::String(name.replace(
-                            "net.fabricmc:intermediary",
-                            "net.ornithemc:calamus-intermediary",
-                        )),
-                    );
-                    lib_mut.insert(
-                        "url".to_string(),
-                        Value::String("https://maven.ornithemc.net/releases".to_string()),
-                    );
-                }
-                if name.starts_with("org.quiltmc:hashed") {
-                    lib_mut.insert(
-                        "name".to_string(),
-                        Value::String(
-                            name.replace(
-                                "org.quiltmc:hashed",
-                                "net.ornithemc:calamus-intermediary",
-                            ),
-                        ),
-                    );
-                    lib_mut.insert(
-                        "url".to_string(),
-                        Value::String("https://maven.ornithemc.net/releases".to_string()),
-                    );
+    for library in &mut profile.libraries {
+        loader_type.rewrite_library(library)?;
+    }
+    Ok(profile)
+}
+
+/// Fetches the versions of all known loaders that support the given game version.
+/// Loaders without any compatible versions are left out.
+pub async fn fetch_compatible_loader_versions(
+    version: &MinecraftVersion,
+    side: GameSide,
+) -> Result<HashMap<LoaderType, Vec<LoaderVersion>>, InstallerError> {
+    fetch_all_loaders(|loader| async move {
+        fetch_compatible_loader_versions_type(&loader, version, side).await
+    })
+    .await
+}
+
+/// Fetches the versions of every known loader. Loaders whose versions
+/// cannot be fetched are left out, unless none of them are available.
+async fn fetch_all_loaders<F, Fut>(
+    fetch: F,
+) -> Result<HashMap<LoaderType, Vec<LoaderVersion>>, InstallerError>
+where
+    F: Fn(LoaderType) -> Fut,
+    Fut: Future<Output = Result<Vec<LoaderVersion>, InstallerError>>,
+{
+    let mut out = HashMap::new();
+    let mut last_error = None;
+    for loader in LoaderType::all() {
+        match fetch(loader.clone()).await {
+            Ok(versions) => {
+                if !versions.is_empty() {
+                    out.insert(loader.clone(), versions);
                 }
             }
+            Err(e) => {
+                warn!(
+                    "Failed to fetch {} Loader versions: {}",
+                    loader.get_localized_name(),
+                    e.report()
+                );
+                last_error = Some(e);
+            }
         }
     }
-    Ok(serde_json::to_string_pretty(&text)?)
+    match last_error {
+        Some(e) if out.is_empty() => Err(e),
+        _ => Ok(out),
+    }
 }
 
-pub async fn fetch_loader_versions()
--> Result<HashMap<LoaderType, Vec<LoaderVersion>>, InstallerError> {
-    let mut out = HashMap::new();
-    for loader in [LoaderType::Fabric, LoaderType::Quilt] {
-        let versions = fetch_loader_versions_type(&loader).await?;
-        out.insert(loader, versions);
-    }
-    Ok(out)
+/// Fetches the versions of a loader, sorted newest first
+pub async fn fetch_loader_versions_type(
+    loader_type: &LoaderType,
+) -> Result<Vec<LoaderVersion>, InstallerError> {
+    let mut versions = loader_type.fetch::<Vec<LoaderVersion>>("").await?;
+    sort_loader_versions(&mut versions);
+    Ok(versions)
 }
 
-async fn fetch_loader_versions_type(
+/// Fetches the versions of a loader that support the given game version, sorted newest first
+pub async fn fetch_compatible_loader_versions_type(
     loader_type: &LoaderType,
+    version: &MinecraftVersion,
+    side: GameSide,
 ) -> Result<Vec<LoaderVersion>, InstallerError> {
-    let url = META_URL.to_owned()
-        + "/v3/versions/"
-        + match loader_type {
-            LoaderType::Fabric => "fabric-loader",
-            LoaderType::Quilt => "quilt-loader",
-        };
-    super::CLIENT
-        .get(url)
-        .send()
+    let mut versions = loader_type
+        .fetch::<Vec<GameLoaderVersion>>(&format!("/{}", version.get_id(&side).await?))
         .await?
-        .json::<Vec<LoaderVersion>>()
-        .await
-        .map_err(|e| e.into())
+        .into_iter()
+        .map(|v| v.loader)
+        .collect::<Vec<_>>();
+    sort_loader_versions(&mut versions);
+    Ok(versions)
+}
+
+fn sort_loader_versions(versions: &mut [LoaderVersion]) {
+    // Versions that are not valid semver go last, ordered by build number
+    versions.sort_by(|a, b| {
+        b.semver()
+            .cmp(&a.semver())
+            .then_with(|| b.build.cmp(&a.build))
+    });
 }
 
 #[allow(dead_code)]
@@ -163,13 +347,11 @@ pub struct IntermediaryVersion {
 
 pub async fn fetch_intermediary_versions()
 -> Result<HashMap<String, IntermediaryVersion>, InstallerError> {
-    let versions = super::CLIENT
-        .get(META_URL.to_owned() + "/v3/versions/intermediary")
-        .send()
-        .await?
-        .json::<Vec<IntermediaryVersion>>()
-        .await
-        .map_err(|e| Into::<InstallerError>::into(e))?;
+    let versions = super::get_mirrored::<Vec<IntermediaryVersion>>(
+        &crate::config::get().meta_urls,
+        "/v3/versions/intermediary",
+    )
+    .await?;
     let mut out = HashMap::with_capacity(versions.len());
     for ver in versions {
         out.insert(ver.version.clone(), ver);
@@ -177,37 +359,30 @@ pub async fn fetch_intermediary_versions()
     Ok(out)
 }
 
-#[allow(dead_code)]
-#[derive(Deserialize)]
-struct ProfileJson {
-    id: String,
-    libraries: Vec<ProfileJsonLibrary>,
-}
-
-#[derive(Deserialize)]
-pub struct ProfileJsonLibrary {
-    pub name: String,
-    pub url: String,
+/// Launch json of a loader version, as served by the Ornithe meta
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LaunchProfile {
+    pub id: String,
+    pub main_class: String,
+    /// Main class of the server launcher, only provided by Quilt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub launcher_main_class: Option<String>,
+    pub libraries: Vec<Library>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 pub async fn fetch_profile_libraries(
     version: &IntermediaryVersion,
     loader_type: &LoaderType,
     loader_version: &LoaderVersion,
-) -> Result<Vec<ProfileJsonLibrary>, InstallerError> {
-    let profile = super::CLIENT
-        .get(
-            META_URL.to_owned()
-                + &format!(
-                    "/v3/versions/{}-loader/{}/{}/profile/json",
-                    loader_type.get_name(),
-                    version.version,
-                    loader_version.version
-                ),
-        )
-        .send()
-        .await?
-        .json::<ProfileJson>()
+) -> Result<Vec<Library>, InstallerError> {
+    let profile = loader_type
+        .fetch::<LaunchProfile>(&format!(
+            "/{}/{}/profile/json",
+            version.version, loader_version.version
+        ))
         .await?;
 
     let mut out = Vec::new();
@@ -219,10 +394,127 @@ pub async fn fetch_profile_libraries(
             continue;
         }
 
-        if lib.name.starts_with(loader_type.get_maven_name_start()) {
+        if lib.coordinate()?.module() == loader_type.get_maven_module() {
             loader_found = true;
         }
     }
 
     Ok(out)
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::errors::InstallerError;
+
+    use super::{
+        BUILTIN_LOADERS, LoaderType, LoaderVersion, select_loader_version, sort_loader_versions,
+    };
+
+    fn loader_version(version: &str, stable: bool, build: i32) -> LoaderVersion {
+        serde_json::from_value(json!({
+            "version": version,
+            "stable": stable,
+            "maven": format!("net.fabricmc:fabric-loader:{}", version),
+            "separator": ".",
+            "build": build
+        }))
+        .unwrap()
+    }
+
+    /// Loader versions sorted newest first
+    fn versions() -> Vec<LoaderVersion> {
+        let mut versions = vec![
+            loader_version("0.16.9", true, 9),
+            loader_version("custom", false, 1),
+            loader_version("0.17.0-beta.1", false, 11),
+            loader_version("0.15.11", true, 8),
+            loader_version("0.16.10", true, 10),
+            loader_version("nightly", false, 2),
+        ];
+        sort_loader_versions(&mut versions);
+        versions
+    }
+
+    fn select(selector: &str) -> Result<String, InstallerError> {
+        select_loader_version(&versions(), selector).map(|version| version.version.clone())
+    }
+
+    #[test]
+    fn sorts_newest_first() {
+        let versions = versions()
+            .into_iter()
+            .map(|version| version.version)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            versions,
+            [
+                "0.17.0-beta.1",
+                "0.16.10",
+                "0.16.9",
+                "0.15.11",
+                "nightly",
+                "custom"
+            ]
+        );
+    }
+
+    #[test]
+    fn selects_latest_versions() {
+        assert_eq!(select("latest").unwrap(), "0.17.0-beta.1");
+        assert_eq!(select("latest-stable").unwrap(), "0.16.10");
+    }
+
+    #[test]
+    fn selects_exact_versions() {
+        assert_eq!(select("0.16.9").unwrap(), "0.16.9");
+        assert_eq!(select(" 0.15.11 ").unwrap(), "0.15.11");
+        assert_eq!(select("custom").unwrap(), "custom");
+        assert!(matches!(select("0.1.0"), Err(InstallerError::NotFound(_))));
+    }
+
+    #[test]
+    fn selects_newest_stable_version_in_range() {
+        assert_eq!(select(">=0.16, <0.17").unwrap(), "0.16.10");
+        assert_eq!(select("<0.16.10").unwrap(), "0.16.9");
+        // Unstable versions are only selected if nothing stable matches
+        assert_eq!(select(">=0.17.0-beta.0").unwrap(), "0.17.0-beta.1");
+        assert!(matches!(select(">=1.0"), Err(InstallerError::NotFound(_))));
+        assert!(matches!(
+            select("not a version"),
+            Err(InstallerError::UserInput(_))
+        ));
+    }
+
+    fn builtin_loaders() -> Vec<LoaderType> {
+        serde_json::from_str(BUILTIN_LOADERS).unwrap()
+    }
+
+    #[test]
+    fn builtin_loaders_are_valid() {
+        for loader in builtin_loaders() {
+            assert!(loader.validate().is_ok(), "{}", loader.name);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_loaders() {
+        let loader = builtin_loaders().remove(0);
+
+        let mut invalid = loader.clone();
+        invalid.name = "../fabric".to_owned();
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = loader.clone();
+        invalid.maven_module = "net.fabricmc:fabric-loader:0.16.10".to_owned();
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = loader;
+        invalid.library_rewrites = serde_json::from_str(
+            r#"[{ "from": "net.fabricmc:intermediary", "to": "net.ornithemc" }]"#,
+        )
+        .unwrap();
+        assert!(invalid.validate().is_err());
+    }
+}
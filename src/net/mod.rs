@@ -1,34 +1,456 @@
-use std::{path::PathBuf, sync::LazyLock};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::LazyLock,
+    time::Duration,
+};
 
-use reqwest::Client;
+use log::{debug, warn};
+use reqwest::{Certificate, Client, NoProxy, Proxy, StatusCode, header::RANGE, redirect};
+use serde::de::DeserializeOwned;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
 
-use crate::errors::InstallerError;
+use crate::{
+    errors::{InstallerError, PathContext, VerificationError},
+    progress::{Progress, ProgressEvent},
+};
 
+pub mod cache;
+pub mod library;
+pub mod limits;
 pub mod manifest;
 pub mod meta;
+pub mod retry;
 
-static CLIENT: LazyLock<Client> = LazyLock::new(|| {
-    Client::builder()
+static CLIENT: LazyLock<Result<Client, String>> =
+    LazyLock::new(|| build_client().map_err(|e| e.to_string()));
+
+fn build_client() -> Result<Client, InstallerError> {
+    let config = crate::config::get();
+    let mut builder = Client::builder()
         .user_agent(crate::USER_AGENT)
-        .build()
-        .unwrap()
-});
+        .connect_timeout(Duration::from_secs(config.connect_timeout))
+        .read_timeout(Duration::from_secs(config.read_timeout))
+        .redirect(redirect_policy());
+
+    if let Some(url) = &config.proxy {
+        let proxy = Proxy::all(url)
+            .map_err(|e| InstallerError::UserInput(format!("Invalid proxy {}: {}", url, e)))?
+            .no_proxy(config.no_proxy.as_deref().and_then(NoProxy::from_string));
+        builder = builder.proxy(proxy);
+    }
+
+    if let Some(path) = &config.ca_bundle {
+        let pem = std::fs::read(path).with_path(path)?;
+        let certificates = Certificate::from_pem_bundle(&pem).map_err(|e| {
+            InstallerError::UserInput(format!("Invalid CA bundle {}: {}", path.display(), e))
+        })?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Follows redirects only to urls that could have been fetched directly
+fn redirect_policy() -> redirect::Policy {
+    redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= 10 {
+            return attempt.error("too many redirects");
+        }
+        match crate::security::check_url(attempt.url().as_str()) {
+            Ok(()) => attempt.follow(),
+            Err(e) => attempt.error(e),
+        }
+    })
+}
+
+/// The shared HTTP client, configured on first use.
+pub fn client() -> Result<&'static Client, InstallerError> {
+    CLIENT
+        .as_ref()
+        .map_err(|e| InstallerError::UserInput("Failed to set up HTTP client: ".to_owned() + e))
+}
+
+/// Requests `path` from each of the given mirrors in order,
+/// returning the first successfully parsed response.
+pub async fn get_mirrored<T: DeserializeOwned>(
+    mirrors: &[String],
+    path: &str,
+) -> Result<T, InstallerError> {
+    let timeout = Duration::from_secs(crate::config::get().timeout);
+    let mut last_error = None;
+    for mirror in mirrors {
+        let url = mirror.trim_end_matches('/').to_owned() + path;
+        match cache::get(&url, timeout).await {
+            Ok(body) => {
+                return serde_json::from_slice(&body).map_err(|e| InstallerError::Metadata {
+                    message: format!("Invalid response from {}", url),
+                    source: Some(Box::new(e)),
+                });
+            }
+            Err(e) => {
+                if !crate::config::get().offline {
+                    warn!("Failed to fetch {}: {}", url, e.report());
+                }
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or(InstallerError::UserInput(
+        "No mirrors configured for ".to_owned() + path,
+    )))
+}
+
+/// Like [get_mirrored], but for absolute urls. If the url belongs to
+/// one of the mirrors (or the default endpoint) it is resolved against
+/// every mirror, otherwise it is requested as-is.
+pub async fn get_mirrored_url<T: DeserializeOwned>(
+    mirrors: &[String],
+    default: &str,
+    url: &str,
+) -> Result<T, InstallerError> {
+    let path = mirrors
+        .iter()
+        .map(|m| m.trim_end_matches('/'))
+        .chain([default])
+        .find_map(|base| url.strip_prefix(base).filter(|p| p.starts_with('/')));
+    match path {
+        Some(path) => get_mirrored(mirrors, path).await,
+        None => get_mirrored(&[url.to_owned()], "").await,
+    }
+}
+
+/// Expected hash and (if known) size of a file
+#[derive(Clone, Debug)]
+pub enum Checksum {
+    Sha1 { hash: String, size: Option<u64> },
+    Sha256 { hash: String, size: Option<u64> },
+}
+
+impl Checksum {
+    pub fn sha1(hash: &str, size: u64) -> Checksum {
+        Checksum::Sha1 {
+            hash: hash.to_owned(),
+            size: Some(size),
+        }
+    }
+
+    fn size(&self) -> Option<u64> {
+        match self {
+            Checksum::Sha1 { size, .. } | Checksum::Sha256 { size, .. } => *size,
+        }
+    }
+
+    fn hash(&self) -> &str {
+        match self {
+            Checksum::Sha1 { hash, .. } | Checksum::Sha256 { hash, .. } => hash,
+        }
+    }
+
+    fn algorithm(&self) -> &str {
+        match self {
+            Checksum::Sha1 { .. } => "sha1",
+            Checksum::Sha256 { .. } => "sha256",
+        }
+    }
+
+    /// Checks the size and hash of a file, computed with [Hasher::for_checksum]
+    fn verify(&self, url: &str, size: u64, hash: &str) -> Result<(), VerificationError> {
+        if let Some(expected) = self.size()
+            && expected != size
+        {
+            return Err(VerificationError {
+                url: url.to_owned(),
+                expected: format!("{} bytes", expected),
+                actual: format!("{} bytes", size),
+            });
+        }
+        if !hash.eq_ignore_ascii_case(self.hash()) {
+            return Err(VerificationError {
+                url: url.to_owned(),
+                expected: format!("{} {}", self.algorithm(), self.hash()),
+                actual: format!("{} {}", self.algorithm(), hash),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Incrementally computes the hash a [Checksum] expects
+enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    /// SHA-1 is used if there is no checksum
+    fn for_checksum(checksum: Option<&Checksum>) -> Hasher {
+        match checksum {
+            Some(Checksum::Sha256 { .. }) => Hasher::Sha256(Sha256::new()),
+            _ => Hasher::Sha1(Sha1::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            Hasher::Sha1(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+        }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
 
-pub async fn download_file(url: &str, output: &PathBuf) -> Result<(), InstallerError> {
-    let bytes = CLIENT.get(url).send().await?.bytes().await?;
-    if let Some(parent) = output.parent() {
-        if !std::fs::exists(parent)? {
-            std::fs::create_dir_all(parent)?;
+/// Fetches the `.sha256` or, failing that, the `.sha1` file maven repositories
+/// publish next to each artifact. If neither exists, a warning is logged and
+/// the artifact has to be downloaded unverified.
+pub async fn fetch_maven_checksum(url: &str) -> Option<Checksum> {
+    let timeout = Duration::from_secs(crate::config::get().timeout);
+    let mut last_error = None;
+    for extension in [".sha256", ".sha1"] {
+        match cache::get(&(url.to_owned() + extension), timeout).await {
+            Ok(body) => {
+                let hash = String::from_utf8_lossy(&body)
+                    .split_whitespace()
+                    .next()
+                    .map(str::to_owned);
+                if let Some(hash) = hash {
+                    return Some(match extension {
+                        ".sha256" => Checksum::Sha256 { hash, size: None },
+                        _ => Checksum::Sha1 { hash, size: None },
+                    });
+                }
+            }
+            Err(e) => last_error = Some(e),
         }
     }
-    if std::fs::exists(output).unwrap_or(false) {
-        std::fs::remove_file(output)?;
+    match last_error {
+        Some(e) => warn!("No checksum available for {}: {}", url, e.report()),
+        None => warn!("No checksum available for {}", url),
+    }
+    None
+}
+
+/// Downloads `url` to `output`. The file is streamed to a temporary file
+/// next to the output, which is only moved into place once verified.
+/// Left-over temporary files from interrupted downloads are resumed.
+pub async fn download_file(
+    url: &str,
+    output: &Path,
+    checksum: Option<&Checksum>,
+    progress: &Progress,
+) -> Result<(), InstallerError> {
+    let _permit = limits::acquire_download().await;
+    fetch_file(url, output, checksum, progress).await
+}
+
+/// Like [download_file], but keeps an existing file that matches the checksum.
+pub async fn download_file_if_changed(
+    url: &str,
+    output: &Path,
+    checksum: Option<&Checksum>,
+    progress: &Progress,
+) -> Result<(), InstallerError> {
+    if let Some(checksum) = checksum
+        && is_intact(url, output, checksum)
+    {
+        debug!("{} is up to date", output.display());
+        return Ok(());
+    }
+    download_file(url, output, checksum, progress).await
+}
+
+/// Downloads a maven artifact, verified against the checksum the repository
+/// publishes next to it (see [fetch_maven_checksum]). An existing file that
+/// matches it is kept. The checksum is fetched within the same download
+/// slot, so that it is bounded by the download limit as well.
+pub async fn download_maven_artifact(
+    url: &str,
+    output: &Path,
+    progress: &Progress,
+) -> Result<(), InstallerError> {
+    let _permit = limits::acquire_download().await;
+    let checksum = fetch_maven_checksum(url).await;
+    if let Some(checksum) = &checksum
+        && is_intact(url, output, checksum)
+    {
+        debug!("{} is up to date", output.display());
+        return Ok(());
+    }
+    fetch_file(url, output, checksum.as_ref(), progress).await
+}
+
+/// Whether the file exists and matches the checksum
+pub fn is_intact(url: &str, output: &Path, checksum: &Checksum) -> bool {
+    output.is_file()
+        && hash_file(output, Some(checksum))
+            .is_ok_and(|(size, hash)| checksum.verify(url, size, &hash).is_ok())
+}
+
+/// Performs a download, see [download_file]. The caller holds the download slot.
+async fn fetch_file(
+    url: &str,
+    output: &Path,
+    checksum: Option<&Checksum>,
+    progress: &Progress,
+) -> Result<(), InstallerError> {
+    crate::security::check_url(url)?;
+    if let Some(parent) = output.parent()
+        && !std::fs::exists(parent)?
+    {
+        std::fs::create_dir_all(parent).with_path(parent)?;
+    }
+    let mut part_name = output.file_name().unwrap_or_default().to_owned();
+    part_name.push(".part");
+    let part = output.with_file_name(part_name);
+
+    let config = crate::config::get();
+    let (size, hash) = if config.offline {
+        cache::restore_artifact(url, &part)?;
+        hash_file(&part, checksum)?
+    } else {
+        let resumed = part.exists();
+        let (size, hash) = stream_to_file(url, &part, checksum, progress).await?;
+        match checksum {
+            Some(checksum) if resumed && checksum.verify(url, size, &hash).is_err() => {
+                warn!("Resumed download of {} is corrupt, restarting", url);
+                std::fs::remove_file(&part)?;
+                stream_to_file(url, &part, Some(checksum), progress).await?
+            }
+            _ => (size, hash),
+        }
+    };
+
+    if let Some(checksum) = checksum
+        && let Err(e) = checksum.verify(url, size, &hash)
+    {
+        std::fs::remove_file(&part)?;
+        return Err(e.into());
+    }
+
+    std::fs::rename(&part, output).with_path(output)?;
+
+    if !config.offline
+        && !config.no_cache
+        && let Err(e) = cache::store_artifact(url, output)
+    {
+        warn!("Failed to cache {}: {}", url, e.report());
     }
-    std::fs::write(output, bytes)?;
 
     Ok(())
 }
 
+/// Appends the remaining bytes of `url` to `part`, returning the final
+/// size and hash of the file, as expected by the checksum. Transfers
+/// interrupted midway are resumed until the retry attempts are exhausted.
+async fn stream_to_file(
+    url: &str,
+    part: &Path,
+    checksum: Option<&Checksum>,
+    progress: &Progress,
+) -> Result<(u64, String), InstallerError> {
+    let attempts = retry::attempts();
+    let mut attempt = 0;
+    'attempt: loop {
+        let existing = std::fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+
+        let mut request = client()?.get(url);
+        if existing > 0 {
+            debug!("Resuming download of {} at {} bytes", url, existing);
+            request = request.header(RANGE, format!("bytes={}-", existing));
+        }
+        let mut res = retry::send(request).await?;
+        if res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            std::fs::remove_file(part)?;
+            res = retry::send(client()?.get(url)).await?;
+        }
+        let mut res = res.error_for_status()?;
+
+        let (mut size, mut hasher, mut file) = if res.status() == StatusCode::PARTIAL_CONTENT {
+            let (size, hasher) = hash_file_state(part, checksum)?;
+            let file = OpenOptions::new().append(true).open(part).with_path(part)?;
+            (size, hasher, file)
+        } else {
+            (
+                0,
+                Hasher::for_checksum(checksum),
+                File::create(part).with_path(part)?,
+            )
+        };
+        if attempt == 0 {
+            progress.send(ProgressEvent::DownloadStarted {
+                total: res.content_length().map(|len| len + size),
+            });
+            progress.send(ProgressEvent::Downloaded(size));
+        }
+
+        loop {
+            match res.chunk().await {
+                Ok(Some(chunk)) => {
+                    limits::throttle(chunk.len() as u64).await;
+                    file.write_all(&chunk)?;
+                    hasher.update(&chunk);
+                    size += chunk.len() as u64;
+                    progress.send(ProgressEvent::Downloaded(chunk.len() as u64));
+                }
+                Ok(None) => break,
+                Err(e) if attempt + 1 < attempts => {
+                    file.sync_all()?;
+                    let delay = retry::backoff(attempt);
+                    warn!(
+                        "Download of {} interrupted ({}), resuming in {:.1}s",
+                        url,
+                        e,
+                        delay.as_secs_f32()
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    continue 'attempt;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        file.sync_all()?;
+
+        return Ok((size, hasher.finalize()));
+    }
+}
+
+fn hash_file(path: &Path, checksum: Option<&Checksum>) -> Result<(u64, String), InstallerError> {
+    let (size, hasher) = hash_file_state(path, checksum)?;
+    Ok((size, hasher.finalize()))
+}
+
+fn hash_file_state(
+    path: &Path,
+    checksum: Option<&Checksum>,
+) -> Result<(u64, Hasher), InstallerError> {
+    let mut hasher = Hasher::for_checksum(checksum);
+    let size = std::io::copy(&mut File::open(path).with_path(path)?, &mut hasher)?;
+    Ok((size, hasher))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum GameSide {
     Client,
     Server,
@@ -42,3 +464,33 @@ impl GameSide {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Checksum, Hasher};
+
+    fn hash(checksum: &Checksum, data: &[u8]) -> String {
+        let mut hasher = Hasher::for_checksum(Some(checksum));
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    #[test]
+    fn verifies_sha1_and_sha256() {
+        let sha1 = Checksum::sha1("A9993E364706816ABA3E25717850C26C9CD0D89D", 3);
+        assert!(sha1.verify("url", 3, &hash(&sha1, b"abc")).is_ok());
+
+        let sha256 = Checksum::Sha256 {
+            hash: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_owned(),
+            size: None,
+        };
+        assert!(sha256.verify("url", 3, &hash(&sha256, b"abc")).is_ok());
+        assert!(sha256.verify("url", 3, &hash(&sha256, b"abd")).is_err());
+    }
+
+    #[test]
+    fn rejects_size_mismatch() {
+        let sha1 = Checksum::sha1("a9993e364706816aba3e25717850c26c9cd0d89d", 4);
+        assert!(sha1.verify("url", 3, &hash(&sha1, b"abc")).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use rand::Rng;
+use reqwest::{RequestBuilder, Response, StatusCode, header::RETRY_AFTER};
+
+use crate::errors::InstallerError;
+
+/// Upper bound for any single delay between attempts
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+pub fn attempts() -> u32 {
+    crate::config::get().retries.max(1)
+}
+
+/// Exponential backoff with equal jitter for the given (zero-based) attempt:
+/// at least half of the exponential delay, plus a random part of the other half.
+pub fn backoff(attempt: u32) -> Duration {
+    backoff_with(crate::config::get().retry_delay, attempt)
+}
+
+fn backoff_with(base: u64, attempt: u32) -> Duration {
+    let max = base
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_DELAY.as_millis() as u64);
+    Duration::from_millis(rand::thread_rng().gen_range(max / 2..=max))
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_timeout() || error.is_connect() || error.is_request() || error.is_body()
+}
+
+fn retry_after(res: &Response) -> Option<Duration> {
+    if res.status() != StatusCode::TOO_MANY_REQUESTS
+        && res.status() != StatusCode::SERVICE_UNAVAILABLE
+    {
+        return None;
+    }
+    let value = res.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    let delay = match value.trim().parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let date = DateTime::parse_from_rfc2822(value).ok()?;
+            (date.with_timezone(&Utc) - Utc::now())
+                .to_std()
+                .unwrap_or_default()
+        }
+    };
+    Some(delay.min(MAX_DELAY))
+}
+
+/// Sends the request, retrying on transient failures and
+/// server errors. The final response is returned as-is.
+pub async fn send(request: RequestBuilder) -> Result<Response, InstallerError> {
+    let attempts = attempts();
+    let mut attempt = 0;
+    loop {
+        let Some(current) = request.try_clone() else {
+            return Ok(request.send().await?);
+        };
+        let last = attempt + 1 >= attempts;
+        match current.send().await {
+            Ok(res) if !last && is_retryable_status(res.status()) => {
+                let delay = retry_after(&res).unwrap_or_else(|| backoff(attempt));
+                warn!(
+                    "{} returned {}, retrying in {:.1}s",
+                    res.url(),
+                    res.status(),
+                    delay.as_secs_f32()
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Ok(res) => return Ok(res),
+            Err(e) if !last && is_retryable_error(&e) => {
+                let delay = backoff(attempt);
+                warn!("{}, retrying in {:.1}s", e, delay.as_secs_f32());
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{MAX_DELAY, backoff_with};
+
+    #[test]
+    fn backoff_stays_within_bounds() {
+        for attempt in 0..20 {
+            let max = Duration::from_millis(500 * (1 << attempt.min(16))).min(MAX_DELAY);
+            for _ in 0..100 {
+                let delay = backoff_with(500, attempt);
+                assert!(
+                    delay >= max / 2 && delay <= max,
+                    "{:?} for attempt {}",
+                    delay,
+                    attempt
+                );
+            }
+        }
+        assert_eq!(backoff_with(0, 3), Duration::ZERO);
+        assert!(backoff_with(u64::MAX, 3) <= MAX_DELAY);
+    }
+}
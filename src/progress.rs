@@ -0,0 +1,99 @@
+use log::info;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    /// A new step of the installation has started
+    Phase(String),
+    /// A download has started, with its total size if known
+    DownloadStarted { total: Option<u64> },
+    /// Bytes have been received for a running download
+    Downloaded(u64),
+    /// The number of libraries about to be downloaded
+    LibrariesQueued(usize),
+    /// A library has been downloaded
+    LibraryCompleted,
+}
+
+/// Handle used by actions to report their progress.
+/// Events are dropped if nobody is listening.
+#[derive(Clone, Default)]
+pub struct Progress(Option<UnboundedSender<ProgressEvent>>);
+
+impl Progress {
+    pub fn channel() -> (Progress, UnboundedReceiver<ProgressEvent>) {
+        let (tx, rx) = unbounded_channel();
+        (Progress(Some(tx)), rx)
+    }
+
+    pub fn send(&self, event: ProgressEvent) {
+        if let Some(tx) = &self.0 {
+            let _ = tx.send(event);
+        }
+    }
+
+    /// Logs and reports the start of a new installation step.
+    pub fn phase(&self, phase: &str) {
+        info!("{}", phase);
+        self.send(ProgressEvent::Phase(phase.to_owned()));
+    }
+}
+
+/// Accumulated state of an installation, built from its progress events
+#[derive(Default, Clone)]
+pub struct ProgressState {
+    pub phase: String,
+    pub bytes_downloaded: u64,
+    pub bytes_total: u64,
+    pub libraries_completed: usize,
+    pub libraries_total: usize,
+}
+
+impl ProgressState {
+    pub fn apply(&mut self, event: ProgressEvent) {
+        match event {
+            ProgressEvent::Phase(phase) => self.phase = phase,
+            ProgressEvent::DownloadStarted { total } => {
+                self.bytes_total += total.unwrap_or_default()
+            }
+            ProgressEvent::Downloaded(bytes) => self.bytes_downloaded += bytes,
+            ProgressEvent::LibrariesQueued(count) => self.libraries_total += count,
+            ProgressEvent::LibraryCompleted => self.libraries_completed += 1,
+        }
+    }
+
+    /// Completed fraction of the downloads, if there are any
+    pub fn fraction(&self) -> Option<f32> {
+        if self.bytes_total > 0 {
+            Some((self.bytes_downloaded as f32 / self.bytes_total as f32).min(1.0))
+        } else if self.libraries_total > 0 {
+            Some(self.libraries_completed as f32 / self.libraries_total as f32)
+        } else {
+            None
+        }
+    }
+
+    /// Describes the download progress, e.g. `3/12 libraries, 1.2/4.0 MiB`
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if self.libraries_total > 0 {
+            parts.push(format!(
+                "{}/{} libraries",
+                self.libraries_completed, self.libraries_total
+            ));
+        }
+        if self.bytes_downloaded > 0 {
+            let mib = |bytes| bytes as f64 / (1024.0 * 1024.0);
+            parts.push(if self.bytes_total > 0 {
+                format!(
+                    "{:.1}/{:.1} MiB",
+                    mib(self.bytes_downloaded),
+                    mib(self.bytes_total)
+                )
+            } else {
+                format!("{:.1} MiB", mib(self.bytes_downloaded))
+            });
+        }
+        parts.join(", ")
+    }
+}
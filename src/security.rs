@@ -0,0 +1,160 @@
+use std::path::{Component, Path, PathBuf};
+
+use reqwest::Url;
+
+use crate::{config::Config, errors::InstallerError};
+
+/// Checks that a url may be fetched. Urls below one of the configured
+/// endpoints are always trusted, anything else (e.g. urls taken from
+/// remote metadata) must use https and point to an allowed host.
+pub fn check_url(url: &str) -> Result<(), InstallerError> {
+    check_url_with(url, crate::config::get())
+}
+
+fn check_url_with(url: &str, config: &Config) -> Result<(), InstallerError> {
+    let trusted = config
+        .meta_urls
+        .iter()
+        .chain(&config.manifest_urls)
+        .chain([&config.maven_url])
+        .chain(config.loaders.iter().map(|loader| &loader.meta_endpoint))
+        .chain(
+            config
+                .loaders
+                .iter()
+                .flat_map(|loader| &loader.library_rewrites)
+                .filter_map(|rewrite| rewrite.repository.as_ref()),
+        )
+        .any(|endpoint| is_below(url, endpoint));
+    if trusted {
+        return Ok(());
+    }
+
+    let parsed = Url::parse(url)
+        .map_err(|e| InstallerError::Security(format!("Refusing to fetch {}: {}", url, e)))?;
+    if parsed.scheme() != "https" {
+        return Err(InstallerError::Security(format!(
+            "Refusing to fetch {} over an insecure connection",
+            url
+        )));
+    }
+    let host = parsed.host_str().unwrap_or_default();
+    let allowed = config.allowed_hosts.iter().any(|allowed| {
+        host == allowed
+            || host
+                .strip_suffix(allowed.as_str())
+                .is_some_and(|sub| sub.ends_with('.'))
+    });
+    if !allowed {
+        return Err(InstallerError::Security(format!(
+            "Refusing to fetch {} as {} is not an allowed host",
+            url, host
+        )));
+    }
+    Ok(())
+}
+
+fn is_below(url: &str, endpoint: &str) -> bool {
+    url.strip_prefix(endpoint.trim_end_matches('/'))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Joins a path taken from remote metadata onto `base`,
+/// rejecting anything that could escape the base directory.
+pub fn safe_join(base: &Path, relative: &str) -> Result<PathBuf, InstallerError> {
+    let path = Path::new(relative);
+    if relative.is_empty()
+        || !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(InstallerError::Security(format!(
+            "Refusing to write to {} outside of {}",
+            relative,
+            base.display()
+        )));
+    }
+    Ok(base.join(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::config::Config;
+
+    use super::{check_url_with, safe_join};
+
+    #[test]
+    fn allows_https_urls_of_allowed_hosts() {
+        let config = Config::default();
+        for url in [
+            "https://ornithemc.net/file.jar",
+            "https://maven.ornithemc.net/releases/file.jar",
+            "https://libraries.minecraft.net/file.jar",
+        ] {
+            assert!(check_url_with(url, &config).is_ok(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn rejects_other_hosts() {
+        let config = Config::default();
+        for url in [
+            "https://evilornithemc.net/file.jar",
+            "https://ornithemc.net.evil.com/file.jar",
+            "https://example.com/file.jar",
+            "not a url",
+        ] {
+            assert!(check_url_with(url, &config).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn rejects_insecure_urls_outside_of_endpoints() {
+        let mut config = Config::default();
+        assert!(check_url_with("http://maven.ornithemc.net/file.jar", &config).is_err());
+
+        config.meta_urls = vec!["http://127.0.0.1:8765/meta".to_owned()];
+        assert!(check_url_with("http://127.0.0.1:8765/meta/v3/versions", &config).is_ok());
+        assert!(check_url_with("http://127.0.0.1:8765/metadata", &config).is_err());
+        assert!(check_url_with("http://127.0.0.1:8765/other", &config).is_err());
+    }
+
+    #[test]
+    fn joins_relative_paths() {
+        let base = Path::new("libraries");
+        assert_eq!(
+            safe_join(base, "net/fabricmc/loader.jar").unwrap(),
+            base.join("net/fabricmc/loader.jar")
+        );
+    }
+
+    #[test]
+    fn rejects_escaping_paths() {
+        let base = Path::new("libraries");
+        for relative in [
+            "",
+            "..",
+            "../loader.jar",
+            "net/../../loader.jar",
+            "./loader.jar",
+            "/etc/passwd",
+        ] {
+            assert!(safe_join(base, relative).is_err(), "{}", relative);
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn rejects_windows_prefixes() {
+        let base = Path::new("libraries");
+        for relative in [
+            r"C:\loader.jar",
+            r"C:loader.jar",
+            r"\\server\share\loader.jar",
+        ] {
+            assert!(safe_join(base, relative).is_err(), "{}", relative);
+        }
+    }
+}
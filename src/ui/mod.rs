@@ -2,6 +2,7 @@ use std::path::PathBuf;
 
 pub mod cli;
 pub mod gui;
+pub mod progress;
 
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub enum Mode {
@@ -10,11 +11,6 @@ pub enum Mode {
     MMC,
 }
 
-fn home_dir() -> Option<PathBuf> {
-    #[allow(deprecated)]
-    std::env::home_dir()
-}
-
 fn location(minecraft_path: Option<PathBuf>, default: &str) -> String {
     use std::env::current_dir;
 
@@ -29,7 +25,7 @@ fn location(minecraft_path: Option<PathBuf>, default: &str) -> String {
 
 #[cfg(all(any(unix), not(target_os = "macos")))]
 pub fn dot_minecraft_location() -> String {
-    location(home_dir().map(|p| p.join(".minecraft")), "/")
+    location(crate::dirs::home_dir().map(|p| p.join(".minecraft")), "/")
 }
 
 #[cfg(target_os = "windows")]
@@ -41,13 +37,13 @@ pub fn dot_minecraft_location() -> String {
 #[cfg(target_os = "macos")]
 pub fn dot_minecraft_location() -> String {
     location(
-        home_dir().map(|p| p.join("Libary/Application Support/minecraft")),
+        crate::dirs::home_dir().map(|p| p.join("Libary/Application Support/minecraft")),
         "/",
     )
 }
 
 fn current_dir(default: &str) -> String {
-    let fallback = home_dir().unwrap_or(PathBuf::from(default));
+    let fallback = crate::dirs::home_dir().unwrap_or(PathBuf::from(default));
     std::env::current_dir()
         .ok()
         .unwrap_or(fallback)
@@ -67,7 +63,7 @@ pub fn current_location() -> String {
 }
 
 fn server_dir(default: &str) -> String {
-    let fallback = home_dir().unwrap_or(PathBuf::from(default));
+    let fallback = crate::dirs::home_dir().unwrap_or(PathBuf::from(default));
     std::env::current_dir()
         .ok()
         .unwrap_or(fallback)
@@ -0,0 +1,84 @@
+use std::{
+    io::{IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::progress::{ProgressEvent, ProgressState};
+
+const BAR_WIDTH: usize = 30;
+/// How often the progress bar is redrawn when attached to a terminal
+const TERMINAL_INTERVAL: Duration = Duration::from_millis(100);
+/// How often a status line is printed otherwise, e.g. in CI logs
+const PLAIN_INTERVAL: Duration = Duration::from_secs(5);
+
+pub fn is_terminal() -> bool {
+    std::io::stderr().is_terminal()
+}
+
+/// Renders progress events to stderr until all senders are dropped.
+/// Uses a progress bar on terminals and periodic status lines otherwise.
+pub async fn render(mut events: UnboundedReceiver<ProgressEvent>) {
+    let terminal = is_terminal();
+    let interval = if terminal {
+        TERMINAL_INTERVAL
+    } else {
+        PLAIN_INTERVAL
+    };
+    let mut state = ProgressState::default();
+    let mut last_draw = Instant::now();
+    let mut last_status = String::new();
+
+    while let Some(event) = events.recv().await {
+        let new_phase = matches!(event, ProgressEvent::Phase(_));
+        if terminal && new_phase && !state.phase.is_empty() {
+            draw_bar(&state);
+            eprintln!();
+        }
+        state.apply(event);
+
+        if (terminal && new_phase) || last_draw.elapsed() >= interval {
+            if terminal {
+                draw_bar(&state);
+            } else {
+                print_status(&state, &mut last_status);
+            }
+            last_draw = Instant::now();
+        }
+    }
+
+    if terminal {
+        if !state.phase.is_empty() {
+            draw_bar(&state);
+            eprintln!();
+        }
+    } else {
+        print_status(&state, &mut last_status);
+    }
+}
+
+fn draw_bar(state: &ProgressState) {
+    let mut line = format!("\r\x1b[2K{}", state.phase);
+    if let Some(fraction) = state.fraction() {
+        let filled = (fraction * BAR_WIDTH as f32) as usize;
+        line += &format!(
+            " [{}{}] {:>3}% {}",
+            "=".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            (fraction * 100.0) as u32,
+            state.describe()
+        );
+    }
+    let mut stderr = std::io::stderr();
+    let _ = stderr.write_all(line.as_bytes());
+    let _ = stderr.flush();
+}
+
+fn print_status(state: &ProgressState, last_status: &mut String) {
+    let status = state.describe();
+    if !status.is_empty() && status != *last_status {
+        eprintln!("{}: {}", state.phase, status);
+        *last_status = status;
+    }
+}